@@ -4,5 +4,361 @@
 
 #![no_std]
 
+/// Boot information passed from the loader to the kernel.
+///
+/// The 32-bit loader and the 64-bit kernel share these structures, so they
+/// use only fixed size integers, and every u64 is placed at an 8 byte
+/// boundary.  Addresses are physical.
+
+use core::mem::{offset_of, size_of};
+use core::str;
+
+
 pub const HEAP_END: usize = 0x01ffffff;
 
+/// "UQBI"
+pub const BOOTINFO_MAGIC: u32 = 0x49425155;
+/// Incremented when the layout is changed.
+pub const BOOTINFO_VERSION: u32 = 1;
+
+pub const MEMORY_REGION_MAX: usize = 128;
+pub const MODULE_MAX: usize = 16;
+pub const CMDLINE_MAX: usize = 256;
+pub const MODULE_CMDLINE_MAX: usize = 64;
+pub const INIT_MAX: usize = 64;
+
+// Bits of BootOptions::console.
+pub const CONSOLE_VGA: u32 = 1 << 0;
+pub const CONSOLE_SERIAL: u32 = 1 << 1;
+
+pub const LOGLEVEL_DEFAULT: u32 = 4;
+
+/// Kind of MemoryRegion.  A larger value wins when regions overlap.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(transparent)]
+pub struct MemoryKind(pub u32);
+
+impl MemoryKind {
+    /// Free RAM.
+    pub const AVAILABLE: MemoryKind = MemoryKind(1);
+    /// Used by the loader.  Free after the kernel takes over boot info.
+    pub const LOADER: MemoryKind = MemoryKind(2);
+    /// Handed off to the kernel: boot info, modules and so on.
+    pub const HANDOFF: MemoryKind = MemoryKind(3);
+    pub const ACPI_RECLAIMABLE: MemoryKind = MemoryKind(4);
+    pub const RESERVED: MemoryKind = MemoryKind(5);
+    pub const ACPI_NVS: MemoryKind = MemoryKind(6);
+    pub const BAD: MemoryKind = MemoryKind(7);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C, align(8))]
+pub struct MemoryRegion {
+    pub start: u64,
+    pub bytes: u64,
+    pub kind: MemoryKind,
+    pub _reserved: u32,
+}
+
+impl MemoryRegion {
+    pub const fn new(start: u64, bytes: u64, kind: MemoryKind) -> Self {
+        MemoryRegion { start, bytes, kind, _reserved: 0 }
+    }
+}
+
+#[derive(Clone, Copy)]
+#[repr(C, align(8))]
+pub struct Module {
+    pub start: u64,
+    pub bytes: u64,
+    pub cmdline: [u8; MODULE_CMDLINE_MAX],
+    pub cmdline_len: u32,
+    pub _reserved: u32,
+}
+
+impl Module {
+    pub fn cmdline(&self) -> &str {
+        str_of(&self.cmdline, self.cmdline_len)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct FramebufferKind(pub u8);
+
+impl FramebufferKind {
+    pub const NONE: FramebufferKind = FramebufferKind(0);
+    pub const INDEXED: FramebufferKind = FramebufferKind(1);
+    pub const RGB: FramebufferKind = FramebufferKind(2);
+    pub const EGA_TEXT: FramebufferKind = FramebufferKind(3);
+}
+
+#[derive(Clone, Copy, Debug)]
+#[repr(C, align(8))]
+pub struct Framebuffer {
+    pub adr: u64,
+    pub pitch: u32,
+    pub width: u32,
+    pub height: u32,
+    pub bpp: u8,
+    pub kind: FramebufferKind,
+    pub _reserved: u16,
+}
+
+/// Options parsed from the command line.
+#[derive(Clone, Copy)]
+#[repr(C, align(8))]
+pub struct BootOptions {
+    /// `mem=`.  Memory above this is not used.  0 if not limited.
+    pub mem_limit: u64,
+    /// `loglevel=`.  Applied by the kernel, not the loader.
+    pub loglevel: u32,
+    /// `console=`.  CONSOLE_* bits.  Applied by the kernel, not the loader.
+    pub console: u32,
+    /// `init=`
+    pub init: [u8; INIT_MAX],
+    pub init_len: u32,
+    pub _reserved: u32,
+}
+
+impl BootOptions {
+    pub fn init(&self) -> &str {
+        str_of(&self.init, self.init_len)
+    }
+
+    /// False if `init` is cut.
+    pub fn set_init(&mut self, init: &str) -> bool {
+        self.init_len = copy_str(&mut self.init, init);
+        self.init_len as usize == init.len()
+    }
+}
+
+#[repr(C, align(8))]
+pub struct BootInfo {
+    pub magic: u32,
+    pub version: u32,
+    /// size_of::<BootInfo>() of the loader.
+    pub size: u32,
+    pub memory_num: u32,
+    /// Sorted and not overlapped.
+    pub memory: [MemoryRegion; MEMORY_REGION_MAX],
+    pub module_num: u32,
+    pub cmdline_len: u32,
+    pub modules: [Module; MODULE_MAX],
+    pub cmdline: [u8; CMDLINE_MAX],
+    pub framebuffer: Framebuffer,
+    /// 0 if not found.
+    pub acpi_rsdp: u64,
+    /// Text logged by the loader.
+    pub log_adr: u64,
+    pub log_bytes: u64,
+    /// PML4 loaded to CR3 when the kernel is entered.
+    pub page_table: u64,
+    /// Physical memory is mapped from this virtual address.
+    pub phys_map_base: u64,
+    pub options: BootOptions,
+}
+
+// The layout must not depend on the target.
+const _: () = assert!(size_of::<MemoryRegion>() == 24);
+const _: () = assert!(size_of::<Module>() == 88);
+const _: () = assert!(size_of::<Framebuffer>() == 24);
+const _: () = assert!(size_of::<BootOptions>() == 88);
+const _: () = assert!(size_of::<BootInfo>() == 4912);
+
+const _: () = assert!(offset_of!(MemoryRegion, start) == 0);
+const _: () = assert!(offset_of!(MemoryRegion, bytes) == 8);
+const _: () = assert!(offset_of!(MemoryRegion, kind) == 16);
+const _: () = assert!(offset_of!(MemoryRegion, _reserved) == 20);
+
+const _: () = assert!(offset_of!(Module, start) == 0);
+const _: () = assert!(offset_of!(Module, bytes) == 8);
+const _: () = assert!(offset_of!(Module, cmdline) == 16);
+const _: () = assert!(offset_of!(Module, cmdline_len) == 80);
+const _: () = assert!(offset_of!(Module, _reserved) == 84);
+
+const _: () = assert!(offset_of!(Framebuffer, adr) == 0);
+const _: () = assert!(offset_of!(Framebuffer, pitch) == 8);
+const _: () = assert!(offset_of!(Framebuffer, width) == 12);
+const _: () = assert!(offset_of!(Framebuffer, height) == 16);
+const _: () = assert!(offset_of!(Framebuffer, bpp) == 20);
+const _: () = assert!(offset_of!(Framebuffer, kind) == 21);
+const _: () = assert!(offset_of!(Framebuffer, _reserved) == 22);
+
+const _: () = assert!(offset_of!(BootOptions, mem_limit) == 0);
+const _: () = assert!(offset_of!(BootOptions, loglevel) == 8);
+const _: () = assert!(offset_of!(BootOptions, console) == 12);
+const _: () = assert!(offset_of!(BootOptions, init) == 16);
+const _: () = assert!(offset_of!(BootOptions, init_len) == 80);
+const _: () = assert!(offset_of!(BootOptions, _reserved) == 84);
+
+const _: () = assert!(offset_of!(BootInfo, magic) == 0);
+const _: () = assert!(offset_of!(BootInfo, version) == 4);
+const _: () = assert!(offset_of!(BootInfo, size) == 8);
+const _: () = assert!(offset_of!(BootInfo, memory_num) == 12);
+const _: () = assert!(offset_of!(BootInfo, memory) == 16);
+const _: () = assert!(offset_of!(BootInfo, module_num) == 3088);
+const _: () = assert!(offset_of!(BootInfo, cmdline_len) == 3092);
+const _: () = assert!(offset_of!(BootInfo, modules) == 3096);
+const _: () = assert!(offset_of!(BootInfo, cmdline) == 4504);
+const _: () = assert!(offset_of!(BootInfo, framebuffer) == 4760);
+const _: () = assert!(offset_of!(BootInfo, acpi_rsdp) == 4784);
+const _: () = assert!(offset_of!(BootInfo, log_adr) == 4792);
+const _: () = assert!(offset_of!(BootInfo, log_bytes) == 4800);
+const _: () = assert!(offset_of!(BootInfo, page_table) == 4808);
+const _: () = assert!(offset_of!(BootInfo, phys_map_base) == 4816);
+const _: () = assert!(offset_of!(BootInfo, options) == 4824);
+
+/// Bytes of `buf` up to `len` as str.  Invalid UTF-8 is cut off.
+fn str_of(buf: &[u8], len: u32) -> &str {
+    let bytes = &buf[..(len as usize).min(buf.len())];
+    match str::from_utf8(bytes) {
+        Ok(s) => s,
+        Err(e) => unsafe {
+            str::from_utf8_unchecked(&bytes[..e.valid_up_to()])
+        },
+    }
+}
+
+/// Copy `s` to `buf` at a char boundary.  Returns the copied bytes.
+fn copy_str(buf: &mut [u8], s: &str) -> u32 {
+    let mut len = s.len().min(buf.len());
+    while !s.is_char_boundary(len) {
+        len -= 1;
+    }
+    buf[..len].copy_from_slice(&s.as_bytes()[..len]);
+    len as u32
+}
+
+impl BootInfo {
+    /// Fill the header and the default options.  Other fields must be
+    /// zero.
+    pub fn init(&mut self) {
+        self.magic = BOOTINFO_MAGIC;
+        self.version = BOOTINFO_VERSION;
+        self.size = size_of::<BootInfo>() as u32;
+        self.options.loglevel = LOGLEVEL_DEFAULT;
+        self.options.console = CONSOLE_VGA;
+    }
+
+    /// True if the kernel can read this.
+    pub fn is_valid(&self) -> bool {
+        self.magic == BOOTINFO_MAGIC &&
+            self.version == BOOTINFO_VERSION &&
+            self.size as usize == size_of::<BootInfo>()
+    }
+
+    pub fn memory(&self) -> &[MemoryRegion] {
+        &self.memory[..(self.memory_num as usize).min(MEMORY_REGION_MAX)]
+    }
+
+    /// False if the table is full.
+    pub fn push_memory(&mut self, region: MemoryRegion) -> bool {
+        let i = self.memory_num as usize;
+        if i >= MEMORY_REGION_MAX {
+            return false;
+        }
+        self.memory[i] = region;
+        self.memory_num += 1;
+        true
+    }
+
+    pub fn clear_memory(&mut self) {
+        self.memory_num = 0;
+    }
+
+    pub fn modules(&self) -> &[Module] {
+        &self.modules[..(self.module_num as usize).min(MODULE_MAX)]
+    }
+
+    /// False if the table is full.  A long `cmdline` is cut.
+    pub fn push_module(&mut self, start: u64, bytes: u64, cmdline: &str)
+        -> bool
+    {
+        let i = self.module_num as usize;
+        if i >= MODULE_MAX {
+            return false;
+        }
+        let m = &mut self.modules[i];
+        m.start = start;
+        m.bytes = bytes;
+        m.cmdline_len = copy_str(&mut m.cmdline, cmdline);
+        self.module_num += 1;
+        true
+    }
+
+    pub fn cmdline(&self) -> &str {
+        str_of(&self.cmdline, self.cmdline_len)
+    }
+
+    /// False if `cmdline` is cut.
+    pub fn set_cmdline(&mut self, cmdline: &str) -> bool {
+        self.cmdline_len = copy_str(&mut self.cmdline, cmdline);
+        self.cmdline_len as usize == cmdline.len()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+    use std::boxed::Box;
+
+    use super::*;
+
+    fn new_info() -> Box<BootInfo> {
+        let mut info: Box<BootInfo> =
+            unsafe { Box::new_zeroed().assume_init() };
+        info.init();
+        info
+    }
+
+    #[test]
+    fn test_header() {
+        let mut info = new_info();
+        assert!(info.is_valid());
+        info.version += 1;
+        assert!(!info.is_valid());
+    }
+
+    #[test]
+    fn test_strings() {
+        let mut info = new_info();
+        assert_eq!(info.cmdline(), "");
+        assert!(info.set_cmdline("root=cd0 debug"));
+        assert_eq!(info.cmdline(), "root=cd0 debug");
+
+        let long = "あ".repeat(CMDLINE_MAX);
+        assert!(!info.set_cmdline(&long));
+        // cut at a char boundary.
+        assert_eq!(info.cmdline().len(), CMDLINE_MAX / 3 * 3);
+
+        for i in 0..MODULE_MAX {
+            assert!(info.push_module(i as u64 * 0x1000, 0x1000, "kernel"));
+        }
+        assert!(!info.push_module(0, 0x1000, "extra"));
+        assert_eq!(info.modules().len(), MODULE_MAX);
+        assert_eq!(info.modules()[3].start, 0x3000);
+        assert_eq!(info.modules()[3].cmdline(), "kernel");
+
+        assert_eq!(info.options.init(), "");
+        assert!(info.options.set_init("/sbin/init"));
+        assert_eq!(info.options.init(), "/sbin/init");
+        assert_eq!(info.options.console, CONSOLE_VGA);
+    }
+
+    #[test]
+    fn test_memory() {
+        let mut info = new_info();
+        for i in 0..MEMORY_REGION_MAX {
+            let r = MemoryRegion::new(i as u64, 1, MemoryKind::AVAILABLE);
+            assert!(info.push_memory(r));
+        }
+        assert!(!info.push_memory(
+            MemoryRegion::new(0, 1, MemoryKind::RESERVED)));
+        assert_eq!(info.memory().len(), MEMORY_REGION_MAX);
+        info.clear_memory();
+        assert!(info.memory().is_empty());
+        assert!(MemoryKind::BAD > MemoryKind::HANDOFF);
+    }
+}
@@ -15,3 +15,11 @@ def config(x):
     #    }
     #)
 
+    kernel64 = x.outroot('x86_64-uniqos', x.buildmode(), 'kernel64')
+
+    x.build_cargo(kernel64, 'xbuild',
+        pkg='x86_64',
+        triple=x.srcpath('x86_64-uniqos.json'),
+        ldscript=x.srcpath('kernel.ld'),
+        rustflags='-Clink-arg=-nostdlib'
+        )
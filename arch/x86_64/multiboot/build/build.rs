@@ -24,6 +24,9 @@ fn main()
     .include("external/multiboot")
     .file("asm/start.S")
     .file("asm/mb2_header.S")
+    .file("asm/mb_header.S")
+    .file("asm/long_mode.S")
+    .file("asm/irq.S")
     .compile("mb");
 
     println!("cargo:rustc-link-search=native={}", String::from(out_dir));
@@ -0,0 +1,175 @@
+// Uniqos  --  Unique Operating System
+// (c) 2019 KATO Takeshi
+// Released under the MIT license
+
+/// ELF64 kernel image.
+///
+/// PT_LOAD segments are copied to their physical addresses, which must be
+/// below 4GiB and outside of the boot heap.
+
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::mem::size_of;
+use core::ptr;
+
+use util::error::Error;
+use util::ops::{self, PhysAddr};
+
+use super::handoff;
+use super::heap;
+use super::log::log;
+
+
+const ELFMAG: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EV_CURRENT: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_X86_64: u16 = 62;
+const PT_LOAD: u32 = 1;
+
+#[repr(C)]
+#[allow(dead_code)]
+struct Elf64Ehdr {
+    e_ident: [u8; 16],
+    e_type: u16,
+    e_machine: u16,
+    e_version: u32,
+    e_entry: u64,
+    e_phoff: u64,
+    e_shoff: u64,
+    e_flags: u32,
+    e_ehsize: u16,
+    e_phentsize: u16,
+    e_phnum: u16,
+    e_shentsize: u16,
+    e_shnum: u16,
+    e_shstrndx: u16,
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+struct Elf64Phdr {
+    p_type: u32,
+    p_flags: u32,
+    p_offset: u64,
+    p_vaddr: u64,
+    p_paddr: u64,
+    p_filesz: u64,
+    p_memsz: u64,
+    p_align: u64,
+}
+
+/// Loaded PT_LOAD segment.
+#[derive(Clone, Copy, Debug)]
+pub struct Segment {
+    pub virt: u64,
+    pub phys: PhysAddr,
+    /// Includes .bss.
+    pub bytes: u64,
+}
+
+pub struct Image {
+    pub entry: u64,
+    pub segments: Vec<Segment>,
+}
+
+/// The image file given by the bootloader.
+struct File {
+    start: u64,
+    bytes: u64,
+}
+
+impl File {
+    /// Address of `[offset, offset + bytes)` in the file.
+    fn at(&self, offset: u64, bytes: u64) -> Result<usize, Error> {
+        let end = offset.checked_add(bytes).ok_or(Error::Range)?;
+        if end > self.bytes {
+            return Err(Error::Range.context("out of the kernel image"));
+        }
+        Ok((self.start + offset) as usize)
+    }
+
+    fn overlaps(&self, start: u64, bytes: u64) -> bool {
+        start < self.start + self.bytes && self.start < start + bytes
+    }
+}
+
+fn check_header(ehdr: &Elf64Ehdr) -> Result<(), Error> {
+    let ident = &ehdr.e_ident;
+    if ident[..4] != ELFMAG ||
+        ident[4] != ELFCLASS64 ||
+        ident[5] != ELFDATA2LSB ||
+        ident[6] != EV_CURRENT
+    {
+        return Err(Error::Inval.context("not ELF64"));
+    }
+    if ehdr.e_type != ET_EXEC || ehdr.e_machine != EM_X86_64 {
+        return Err(Error::Inval.context("not x86_64 executable"));
+    }
+    if (ehdr.e_phentsize as usize) < size_of::<Elf64Phdr>() {
+        return Err(Error::Inval.context("program header size"));
+    }
+    Ok(())
+}
+
+/// Copy a PT_LOAD segment to its physical address and zero the rest.
+fn load_segment(file: &File, phdr: &Elf64Phdr) -> Result<Segment, Error> {
+    let page = ops::PAGE_SIZE as u64;
+    if phdr.p_filesz > phdr.p_memsz ||
+        phdr.p_vaddr % page != phdr.p_paddr % page
+    {
+        return Err(Error::Inval.context("PT_LOAD"));
+    }
+    // the whole segment must be accessible from the loader.
+    let phys = PhysAddr::new(phdr.p_paddr);
+    let last = phys.checked_add(phdr.p_memsz - 1).ok_or(Error::Range)?;
+    if heap::phys_to_virt(last).is_none() ||
+        file.overlaps(phdr.p_paddr, phdr.p_memsz)
+    {
+        return Err(Error::Range.context("PT_LOAD address"));
+    }
+    let src = file.at(phdr.p_offset, phdr.p_filesz)?;
+
+    handoff::reserve(phys, phdr.p_memsz)
+        .map_err(|e| e.context("kernel segment"))?;
+    let dest = heap::phys_to_virt(phys).ok_or(Error::Range)?.get();
+    unsafe {
+        ptr::copy_nonoverlapping(
+            src as *const u8, dest as *mut u8, phdr.p_filesz as usize);
+        ptr::write_bytes(
+            (dest + phdr.p_filesz as usize) as *mut u8, 0,
+            (phdr.p_memsz - phdr.p_filesz) as usize);
+    }
+
+    Ok(Segment { virt: phdr.p_vaddr, phys, bytes: phdr.p_memsz })
+}
+
+/// Load the kernel image at `[start, start + bytes)`.
+pub fn load(start: u64, bytes: u64) -> Result<Image, Error> {
+    let file = File { start, bytes };
+    let ehdr = unsafe {
+        ptr::read_unaligned(
+            file.at(0, size_of::<Elf64Ehdr>() as u64)? as *const Elf64Ehdr)
+    };
+    check_header(&ehdr)?;
+
+    let mut segments = Vec::new();
+    for i in 0..ehdr.e_phnum as u64 {
+        let offset = ehdr.e_phoff.checked_add(i * ehdr.e_phentsize as u64)
+            .ok_or(Error::Range)?;
+        let phdr = unsafe {
+            ptr::read_unaligned(
+                file.at(offset, size_of::<Elf64Phdr>() as u64)?
+                    as *const Elf64Phdr)
+        };
+        if phdr.p_type != PT_LOAD || phdr.p_memsz == 0 {
+            continue;
+        }
+        let seg = load_segment(&file, &phdr)?;
+        writeln!(log(), "{:?}", seg).unwrap();
+        segments.push(seg);
+    }
+
+    Ok(Image { entry: ehdr.e_entry, segments })
+}
@@ -0,0 +1,329 @@
+// Uniqos  --  Unique Operating System
+// (c) 2019 KATO Takeshi
+// Released under the MIT license
+
+/// BootInfo for the kernel.
+///
+/// Each boot protocol fills BootInfo through this module, and `finish()`
+/// makes the memory map of BootInfo sorted and not overlapped.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::alloc::Layout;
+use core::fmt::Write;
+use core::mem::size_of;
+use core::ptr;
+use core::slice;
+
+use bootinfo::*;
+use util::cmdline::{parse_size, Cmdline};
+use util::error::Error;
+use util::ops::{self, PhysAddr};
+use util::spinlock::SpinLock;
+
+use super::heap;
+use super::log::{self, log};
+
+
+static BOOT_INFO: SpinLock<Option<&'static mut BootInfo>> =
+    SpinLock::new(None);
+
+fn with<R, F>(f: F) -> Result<R, Error>
+    where F: FnOnce(&mut BootInfo) -> R
+{
+    match BOOT_INFO.lock().as_mut() {
+        Some(info) => Ok(f(info)),
+        None => Err(Error::Inval.context("BootInfo is not allocated")),
+    }
+}
+
+/// Allocate BootInfo.  The heap must be ready.
+pub fn init() -> Result<(), Error> {
+    let bytes = size_of::<BootInfo>();
+    let layout = Layout::from_size_align(bytes, ops::PAGE_SIZE)
+        .map_err(|_| Error::Inval)?;
+    let p = heap::alloc_handoff(layout)? as *mut BootInfo;
+
+    let info = unsafe {
+        ptr::write_bytes(p, 0, 1);
+        &mut *p
+    };
+    info.init();
+    *BOOT_INFO.lock() = Some(info);
+
+    add_memory(p as u64, bytes as u64, MemoryKind::HANDOFF)
+}
+
+/// Allocate memory which is kept for the kernel.
+pub fn alloc(layout: Layout) -> Result<*mut u8, Error> {
+    let p = heap::alloc_handoff(layout)?;
+    add_memory(p as u64, layout.size() as u64, MemoryKind::HANDOFF)?;
+    Ok(p)
+}
+
+/// Reserve memory which is kept for the kernel.
+pub fn reserve(adr: PhysAddr, bytes: u64) -> Result<(), Error> {
+    if bytes > usize::MAX as u64 {
+        return Err(Error::Range);
+    }
+    heap::reserve_handoff(adr, bytes as usize)?;
+    add_memory(adr.get(), bytes, MemoryKind::HANDOFF)
+}
+
+/// MemoryKind of the memory type in multiboot and multiboot2 memory maps.
+pub fn firmware_kind(typ: u32) -> MemoryKind {
+    match typ {
+        1 => MemoryKind::AVAILABLE,
+        3 => MemoryKind::ACPI_RECLAIMABLE,
+        4 => MemoryKind::ACPI_NVS,
+        5 => MemoryKind::BAD,
+        _ => MemoryKind::RESERVED,
+    }
+}
+
+/// Add a region to the memory map.  Overlaps are resolved by `finish()`.
+pub fn add_memory(start: u64, bytes: u64, kind: MemoryKind)
+    -> Result<(), Error>
+{
+    if bytes == 0 {
+        return Ok(());
+    }
+    let region = MemoryRegion::new(start, bytes, kind);
+    with(|info| push_memory(info, region))?
+}
+
+/// Add available areas of `memmap`.
+pub fn add_memory_map(memmap: &heap::MemoryMap) -> Result<(), Error> {
+    for r in memmap.iter() {
+        let bytes = r.bytes().unwrap_or(u64::MAX);
+        add_memory(r.start().get(), bytes, MemoryKind::AVAILABLE)?;
+    }
+    Ok(())
+}
+
+/// The module is handed off to the kernel.
+pub fn add_module(start: u64, bytes: u64, cmdline: &str)
+    -> Result<(), Error>
+{
+    if !with(|info| info.push_module(start, bytes, cmdline))? {
+        return Err(Error::NoSpace.context("BootInfo modules"));
+    }
+    add_memory(start, bytes, MemoryKind::HANDOFF)
+}
+
+/// Store `cmdline` and the options parsed from it.
+pub fn set_cmdline(cmdline: &str) -> Result<(), Error> {
+    if !with(|info| info.set_cmdline(cmdline))? {
+        write!(log(), "Command line is cut: {}\n", cmdline).unwrap();
+    }
+    let mut options = with(|info| info.options)?;
+    parse_options(&mut options, Cmdline::new(cmdline));
+    with(|info| info.options = options)
+}
+
+/// Unknown keys are left to the kernel.  `loglevel` and `console` are
+/// applied by the kernel; the loader logs everything to VGA, which is its
+/// only output.
+fn parse_options(options: &mut BootOptions, cmdline: Cmdline) {
+    for arg in cmdline {
+        let value = match arg.value {
+            Some(value) => value,
+            None => continue,
+        };
+        let ok = match arg.key {
+            "loglevel" => value.parse().map(|n| options.loglevel = n).is_ok(),
+            "console" => {
+                options.console = 0;
+                for item in arg.items() {
+                    match item {
+                        "vga" => options.console |= CONSOLE_VGA,
+                        "serial" => options.console |= CONSOLE_SERIAL,
+                        _ => write!(log(), "Unknown console: {}\n", item)
+                            .unwrap(),
+                    }
+                }
+                true
+            },
+            "mem" => parse_size(value)
+                .map(|n| options.mem_limit = n).is_some(),
+            "init" => options.set_init(value),
+            _ => true,
+        };
+        if !ok {
+            write!(log(), "Invalid option: {}={}\n", arg.key, value).unwrap();
+        }
+    }
+}
+
+pub fn set_framebuffer(fb: Framebuffer) -> Result<(), Error> {
+    with(|info| info.framebuffer = fb)
+}
+
+pub fn set_paging(page_table: PhysAddr, phys_map_base: u64)
+    -> Result<(), Error>
+{
+    with(|info| {
+        info.page_table = page_table.get();
+        info.phys_map_base = phys_map_base;
+    })
+}
+
+/// End of the memory map added so far.
+pub fn memory_end() -> Result<u64, Error> {
+    with(|info| {
+        info.memory().iter()
+            .map(|r| r.start.saturating_add(r.bytes))
+            .max()
+            .unwrap_or(0)
+    })
+}
+
+/// (start, bytes) of the i-th module.
+pub fn module(i: usize) -> Result<Option<(u64, u64)>, Error> {
+    with(|info| info.modules().get(i).map(|m| (m.start, m.bytes)))
+}
+
+/// Region as [start, end).
+#[derive(Clone, Copy)]
+struct Span {
+    start: u64,
+    end: u64,
+    kind: MemoryKind,
+}
+
+fn is_firmware_kind(kind: MemoryKind) -> bool {
+    kind != MemoryKind::LOADER && kind != MemoryKind::HANDOFF
+}
+
+fn push_memory(info: &mut BootInfo, region: MemoryRegion)
+    -> Result<(), Error>
+{
+    if info.push_memory(region) {
+        Ok(())
+    } else {
+        Err(Error::NoSpace.context("BootInfo memory map"))
+    }
+}
+
+/// Rebuild `info.memory` from `spans`.  Where spans overlap, the larger
+/// MemoryKind wins.  Areas not covered by the firmware map are dropped.
+fn sanitize(info: &mut BootInfo, spans: &[Span]) -> Result<(), Error> {
+    let mut bounds: Vec<u64> = Vec::with_capacity(spans.len() * 2);
+    for s in spans {
+        bounds.push(s.start);
+        bounds.push(s.end);
+    }
+    bounds.sort_unstable();
+    bounds.dedup();
+
+    info.clear_memory();
+    let mut last: Option<MemoryRegion> = None;
+    for w in bounds.windows(2) {
+        let (start, end) = (w[0], w[1]);
+        let covers = spans.iter()
+            .filter(|s| s.start <= start && end <= s.end);
+        let mut kind = None;
+        let mut firmware = false;
+        for s in covers {
+            firmware |= is_firmware_kind(s.kind);
+            kind = kind.max(Some(s.kind));
+        }
+        let kind = match kind {
+            Some(kind) if firmware => kind,
+            _ => continue,
+        };
+
+        match last.as_mut() {
+            Some(r) if r.kind == kind && r.start + r.bytes == start => {
+                r.bytes += end - start;
+            },
+            _ => {
+                if let Some(r) = last {
+                    push_memory(info, r)?;
+                }
+                last = Some(MemoryRegion::new(start, end - start, kind));
+            },
+        }
+    }
+    match last {
+        Some(r) => push_memory(info, r),
+        None => Ok(()),
+    }
+}
+
+/// Check "RSD PTR " and the checksum of ACPI 1.0 part.
+fn is_rsdp(adr: usize) -> bool {
+    let rsdp = unsafe { slice::from_raw_parts(adr as *const u8, 20) };
+    &rsdp[..8] == b"RSD PTR " &&
+        rsdp.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0
+}
+
+/// Keep the copy of the RSDP given by the boot protocol.  It is used
+/// instead of searching the BIOS areas, which UEFI may not have.
+#[cfg(feature = "boot_multiboot2")]
+pub fn set_rsdp(rsdp: &[u8]) -> Result<(), Error> {
+    if rsdp.len() < 20 || !is_rsdp(rsdp.as_ptr() as usize) {
+        return Err(Error::Inval.context("RSDP"));
+    }
+    let layout = Layout::from_size_align(rsdp.len(), 16)
+        .map_err(|_| Error::Inval)?;
+    let p = alloc(layout)?;
+    unsafe { ptr::copy_nonoverlapping(rsdp.as_ptr(), p, rsdp.len()) };
+    with(|info| info.acpi_rsdp = p as u64)
+}
+
+/// Search ACPI RSDP in the first 1KiB of EBDA and the BIOS ROM area.
+fn find_rsdp() -> Option<u64> {
+    let ebda = unsafe { ptr::read(0x40e as *const u16) } as usize * 16;
+    let areas = [(ebda, ebda + 0x400), (0xe0000, 0x100000)];
+    for &(start, end) in areas.iter() {
+        if start == 0 {
+            continue;
+        }
+        for adr in (start..end).step_by(16) {
+            if is_rsdp(adr) {
+                return Some(adr as u64);
+            }
+        }
+    }
+    None
+}
+
+/// Complete BootInfo and return its address.
+pub fn finish() -> Result<PhysAddr, Error> {
+    // the heap is locked while the ranges are taken, so the buffer is
+    // allocated before.
+    let mut used = vec![(PhysAddr::new(0), 0); heap::used_count() + 1];
+    let used_num = heap::used_ranges(&mut used);
+
+    let (log_adr, log_bytes) = log::mem_log();
+
+    let mut guard = BOOT_INFO.lock();
+    let info = guard.as_mut()
+        .ok_or(Error::Inval.context("BootInfo is not allocated"))?;
+
+    let mut spans: Vec<Span> = info.memory().iter()
+        .map(|r| Span {
+            start: r.start,
+            end: r.start.saturating_add(r.bytes),
+            kind: r.kind,
+        })
+        .collect();
+    for &(adr, bytes) in used[..used_num].iter() {
+        spans.push(Span {
+            start: adr.get(),
+            end: adr.get().saturating_add(bytes as u64),
+            kind: MemoryKind::LOADER,
+        });
+    }
+    sanitize(info, &spans)?;
+
+    if info.acpi_rsdp == 0 {
+        info.acpi_rsdp = find_rsdp().unwrap_or(0);
+    }
+    // logs after this are not passed.
+    info.log_adr = log_adr as u64;
+    info.log_bytes = log_bytes as u64;
+
+    Ok(PhysAddr::new(&**info as *const BootInfo as u64))
+}
@@ -3,11 +3,18 @@
 
 /// Heap.
 
+use core::alloc::{GlobalAlloc, Layout};
+use core::cmp::{max, min};
 use core::fmt::Write;
-use core::mem::size_of;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use bootinfo::*;
-use util::cheap_alloc;
+use util::boxed::{self, Dealloc, X};
+use util::cheap_alloc::{self, SlotMask};
+use util::error::Error;
+use util::ops::{PhysAddr, PhysRange, VirtAddr};
+use util::spinlock::{self, IrqOps, IrqSpinLock};
 
 use super::log::log;
 
@@ -16,25 +23,344 @@ pub const SLOT_CONVENTIONAL: usize = 0;
 pub const SLOT_BOOTHEAP: usize = 1;
 pub const SLOT_NORMAL: usize = 2;
 
-const USIZES_IN_ALLOCOBJ: usize = 
-    (size_of::<cheap_alloc::CheapAlloc>() + size_of::<usize>() - 1)
-    / size_of::<usize>();
+/// (slot, start, end) of each slot.  The end is inclusive.
+const SLOT_DEFS: [(usize, PhysAddr, PhysAddr); 3] = [
+    (SLOT_CONVENTIONAL,
+     PhysAddr::new(0x00000000), PhysAddr::new(0x000fffff)),
+    (SLOT_BOOTHEAP,
+     PhysAddr::new(0x00100000), PhysAddr::new(HEAP_END as u64)),
+    (SLOT_NORMAL,
+     PhysAddr::new(HEAP_END as u64 + 1), PhysAddr::new(0xffffffff)),
+];
 
-static mut allocobj: [usize; cheap_alloc::USIZES_IN_CHEAPALLOC] =
-    [0usize; cheap_alloc::USIZES_IN_CHEAPALLOC];
+/// The loader maps memory 1:1, so the heap uses physical addresses as
+/// virtual addresses.  None if `adr` is out of the address space.
+pub fn phys_to_virt(adr: PhysAddr) -> Option<VirtAddr> {
+    if adr.get() > usize::MAX as u64 {
+        None
+    } else {
+        Some(VirtAddr::new(adr.get() as usize))
+    }
+}
 
-fn _get_alloc() -> &'static mut cheap_alloc::CheapAlloc {
-    let rawalloc = unsafe { allocobj.as_mut_ptr() };
-    let rawalloc2 = rawalloc as *mut cheap_alloc::CheapAlloc;
-    unsafe { &mut *rawalloc2 }
+pub fn virt_to_phys(adr: VirtAddr) -> PhysAddr {
+    PhysAddr::new(adr.get() as u64)
+}
+
+static ALLOCOBJ: IrqSpinLock<cheap_alloc::CheapAlloc> =
+    IrqSpinLock::new(cheap_alloc::CheapAlloc::new());
+
+/// GlobalAlloc adapter for ALLOCOBJ.
+pub struct BootHeap {
+    slotmask: AtomicUsize,
+}
+
+#[global_allocator]
+static BOOT_HEAP: BootHeap = BootHeap::new(1 << SLOT_BOOTHEAP);
+
+impl BootHeap {
+    const fn new(slotmask: SlotMask) -> Self {
+        Self {
+            slotmask: AtomicUsize::new(slotmask),
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for BootHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let slotmask = self.slotmask.load(Ordering::Relaxed);
+        // X is converted to a pointer before the lock is released.
+        let r = ALLOCOBJ.lock().alloc::<u8>(slotmask, layout, false);
+        match r {
+            Ok(x) => X::into_raw(x) as *mut u8,
+            Err(_) => ptr::null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
+        let r = ALLOCOBJ.lock().free(VirtAddr::from_ptr(ptr));
+        if r.is_err() {
+            write!(log(), "Free unknown memory: {:p}\n", ptr).unwrap();
+        }
+    }
+}
+
+/// Dealloc adapter for ALLOCOBJ, which frees X with the lock.
+struct XDealloc;
+
+static mut X_DEALLOC: XDealloc = XDealloc;
+
+impl Dealloc for XDealloc {
+    fn dealloc_x(&mut self, slot: usize, adr: usize) {
+        ALLOCOBJ.lock().dealloc_x(slot, adr);
+    }
+}
+
+extern "C" {
+    fn irq_save() -> u32;
+    fn irq_restore(flags: u32);
+}
+
+fn save_irq() -> usize {
+    unsafe { irq_save() as usize }
+}
+
+fn restore_irq(flags: usize) {
+    unsafe { irq_restore(flags as u32) }
+}
+
+/// cli/sti for IrqSpinLock.
+static IRQ_OPS: IrqOps = IrqOps {
+    save: save_irq,
+    restore: restore_irq,
+};
+
+/// Set slots used by `alloc` crate allocations.
+pub fn set_default_slotmask(slotmask: SlotMask) {
+    BOOT_HEAP.slotmask.store(slotmask, Ordering::Relaxed);
 }
 
 pub fn init() {
+    spinlock::set_irq_ops(&IRQ_OPS);
+
     let mut slotdefs = cheap_alloc::SlotDefs::new();
 
-    slotdefs.set(SLOT_CONVENTIONAL, 0x00000000,   0x000fffff);
-    slotdefs.set(SLOT_BOOTHEAP,     0x00100000,   HEAP_END );
-    slotdefs.set(SLOT_NORMAL,       HEAP_END + 1, 0xffffffff);
+    for &(slot, start, end) in SLOT_DEFS.iter() {
+        // the slot is cut at the top of the address space.
+        let end = phys_to_virt(end).unwrap_or(VirtAddr::new(usize::MAX));
+        if let Some(start) = phys_to_virt(start) {
+            slotdefs.set(slot, start, end);
+        }
+    }
+
+    ALLOCOBJ.lock().init_with_slotdefs(&slotdefs);
+    unsafe { boxed::set_default_dealloc(ptr::addr_of_mut!(X_DEALLOC)) };
+}
+
+/// Allocate memory handed off to the kernel.  It is never freed.
+pub fn alloc_handoff(layout: Layout) -> Result<*mut u8, Error> {
+    // forgotten X has no owner.
+    let x = unsafe {
+        ALLOCOBJ.lock().alloc::<u8>(1 << SLOT_BOOTHEAP, layout, true)?
+    };
+    Ok(X::into_raw(x) as *mut u8)
+}
+
+/// Number of ranges allocated or reserved in the heap.
+pub fn used_count() -> usize {
+    let alloc = ALLOCOBJ.lock();
+    SLOT_DEFS.iter().map(|&(slot, _, _)| alloc.used_ranges(slot).count()).sum()
+}
+
+/// Store ranges allocated or reserved in the heap to `buf`, and return the
+/// number of them.  Ranges over `buf` are dropped.
+pub fn used_ranges(buf: &mut [(PhysAddr, usize)]) -> usize {
+    let alloc = ALLOCOBJ.lock();
+    let ranges = SLOT_DEFS.iter()
+        .flat_map(|&(slot, _, _)| alloc.used_ranges(slot));
+    let mut n = 0;
+    for (dst, (adr, bytes)) in buf.iter_mut().zip(ranges) {
+        *dst = (virt_to_phys(adr), bytes);
+        n += 1;
+    }
+    n
+}
+
+/// Bytes of memory added for AdrRange entries after RAM is discovered.
+const RANGE_BUF_GROW: usize = 0x1000;
+
+const MEMORY_MAP_MAX: usize = 32;
+
+/// Sorted available memory areas without overlaps.
+pub struct MemoryMap {
+    areas: [Option<PhysRange>; MEMORY_MAP_MAX],
+    num: usize,
+}
+
+impl MemoryMap {
+    pub fn new() -> Self {
+        Self {
+            areas: [None; MEMORY_MAP_MAX],
+            num: 0,
+        }
+    }
+
+    /// Add an available area.  Overlapped or adjacent areas are merged.
+    pub fn add(&mut self, start: PhysAddr, bytes: u64) -> Result<(), Error> {
+        if bytes == 0 {
+            return Ok(());
+        }
+        let mut start = start.get();
+        let mut end = start.saturating_add(bytes - 1);
+
+        let mut i = 0;
+        while i < self.num {
+            let (s, e) = self._get(i);
+            if s <= end.saturating_add(1) && start <= e.saturating_add(1) {
+                start = min(start, s);
+                end = max(end, e);
+                self._remove(i);
+            } else {
+                i += 1;
+            }
+        }
+
+        let pos = (0..self.num)
+            .position(|j| self._get(j).0 > start)
+            .unwrap_or(self.num);
+        self._insert(pos, start, end)
+    }
+
+    /// Remove an unavailable area.  An area containing it is split.
+    pub fn remove(&mut self, start: PhysAddr, bytes: u64)
+        -> Result<(), Error>
+    {
+        if bytes == 0 {
+            return Ok(());
+        }
+        let start = start.get();
+        let end = start.saturating_add(bytes - 1);
+
+        let mut i = 0;
+        while i < self.num {
+            let (s, e) = self._get(i);
+            if e < start || end < s {
+                i += 1;
+                continue;
+            }
+            self._remove(i);
+            // the upper part first to keep the areas sorted.
+            if end < e {
+                self._insert(i, end + 1, e)?;
+            }
+            if s < start {
+                self._insert(i, s, start - 1)?;
+                i += 1;
+            }
+            if end < e {
+                i += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn _insert(&mut self, i: usize, start: u64, end: u64)
+        -> Result<(), Error>
+    {
+        if self.num >= MEMORY_MAP_MAX {
+            return Err(Error::NoSpace);
+        }
+        for j in (i..self.num).rev() {
+            self.areas[j + 1] = self.areas[j];
+        }
+        self.areas[i] =
+            PhysRange::new(PhysAddr::new(start), PhysAddr::new(end));
+        self.num += 1;
+        Ok(())
+    }
+
+    /// (start, end) of the i-th area.
+    fn _get(&self, i: usize) -> (u64, u64) {
+        let r = self.areas[i].unwrap();
+        (r.start().get(), r.end().get())
+    }
+
+    fn _remove(&mut self, i: usize) {
+        for j in i..self.num - 1 {
+            self.areas[j] = self.areas[j + 1];
+        }
+        self.num -= 1;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = PhysRange> + '_ {
+        self.areas[..self.num].iter().filter_map(|&r| r)
+    }
+}
+
+/// Split `range` at the slot boundaries.
+fn for_each_slot<F>(range: PhysRange, mut f: F) -> Result<(), Error>
+    where F: FnMut(usize, VirtAddr, usize) -> Result<(), Error>
+{
+    for &(slot, slot_start, slot_end) in SLOT_DEFS.iter() {
+        let slot_range = PhysRange::new(slot_start, slot_end).unwrap();
+        let r = match range.intersect(&slot_range) {
+            Some(r) => r,
+            None => continue,
+        };
+        // a slot never covers the whole address space.
+        let bytes = r.bytes().unwrap();
+        if let Some(adr) = phys_to_virt(r.start()) {
+            f(slot, adr, bytes as usize)?;
+        }
+    }
+    Ok(())
+}
+
+/// Register available memory to the heap.  Addresses not covered by any
+/// slot are ignored.
+pub fn add_memory_map(memmap: &MemoryMap) -> Result<(), Error> {
+    for range in memmap.iter() {
+        for_each_slot(range, |slot, adr, bytes| {
+            ALLOCOBJ.lock().add_free_range(slot, adr, bytes)
+        })?;
+    }
+    Ok(())
+}
+
+/// Add more AdrRange entries for fragmented memory.
+/// This should be called after fixed ranges are reserved.
+pub fn grow_range_buf() -> Result<(), Error> {
+    let r = ALLOCOBJ.lock().grow_range_buf(1 << SLOT_BOOTHEAP, RANGE_BUF_GROW);
+    match r {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            write!(log(), "Failed to grow AdrRange entries: {}\n", e).unwrap();
+            Err(e)
+        },
+    }
+}
+
+/// Write the heap state to the log.
+pub fn dump() {
+    write!(log(), "{:?}", *ALLOCOBJ.lock()).unwrap();
+}
+
+fn _reserve(adr: PhysAddr, bytes: usize, forget: bool) -> Result<(), Error> {
+    if bytes == 0 {
+        return Ok(());
+    }
+    let range = PhysRange::from_bytes(adr, bytes as u64).ok_or(Error::Range)?;
+    for_each_slot(range, |slot, adr, bytes| {
+        ALLOCOBJ.lock().reserve(slot, adr, bytes, forget)
+    })
+}
+
+/// Mark `[adr, adr + bytes)` as used.
+pub fn reserve(adr: PhysAddr, bytes: usize) -> Result<(), Error> {
+    _reserve(adr, bytes, false)
+}
+
+/// Mark `[adr, adr + bytes)` as handed off to the kernel.
+pub fn reserve_handoff(adr: PhysAddr, bytes: usize) -> Result<(), Error> {
+    _reserve(adr, bytes, true)
+}
+
+extern "C" {
+    static loader_start: u8;
+    static loader_end: u8;
+}
+
+/// Mark the loader image includes the stack as used.
+pub fn reserve_loader() -> Result<(), Error> {
+    let start = unsafe { VirtAddr::from_ptr(&loader_start) };
+    let end = unsafe { VirtAddr::from_ptr(&loader_end) };
+    let bytes = end.offset_from(start).ok_or(Error::Inval)?;
 
-    _get_alloc().init_with_slotdefs(&slotdefs);
+    let r = reserve(virt_to_phys(start), bytes);
+    if let Err(e) = r {
+        write!(log(), "Failed to reserve loader: {:#x}-{:#x}: {}\n",
+            start, end, e).unwrap();
+    }
+    r
 }
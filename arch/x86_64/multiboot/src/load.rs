@@ -4,9 +4,17 @@
 
 use core::fmt::Write;
 
-use util::error::Error;
+use util::cmdline::{parse_size, Cmdline};
+use util::error::{Code, Error};
+use util::ops::PhysAddr;
+use super::elf;
+use super::handoff;
 use super::heap;
 use super::log::log;
+use super::long_mode;
+use super::mb;
+#[cfg(feature = "boot_multiboot2")]
+use super::mb2;
 
 #[cfg(feature = "boot_multiboot2")]
 extern crate multiboot2;
@@ -14,22 +22,119 @@ extern crate multiboot2;
 
 const MULTIBOOT2_BOOTLOADER_MAGIC: u32 = 0x36d76289;
 
+#[cfg(not(feature = "boot_multiboot2"))]
 fn load_boot_none(_: u32, _: *const u32) -> Result<(), Error> {
-    Err(Error::Fail)
+    Err(Error::Unsupported)
+}
+
+/// Limit of memory given by `mem=`.
+fn mem_limit(cmdline: Option<&str>) -> Option<u64> {
+    Cmdline::new(cmdline?).value("mem").and_then(parse_size)
+}
+
+/// Add `[start, start + bytes)` below `limit` to `memmap`.  Failures are
+/// only logged.
+fn add_memory(
+    memmap: &mut heap::MemoryMap, start: u64, bytes: u64, limit: Option<u64>)
+{
+    let end = start.saturating_add(bytes);
+    let end = limit.map_or(end, |limit| end.min(limit));
+    if start >= end {
+        return;
+    }
+    if let Err(e) = memmap.add(PhysAddr::new(start), end - start) {
+        write!(log(), "Too many memory areas: {}\n", e).unwrap();
+    }
+}
+
+/// The real mode IVT and BIOS data area, which are never used as heap.
+const LOW_RESERVED_BYTES: u64 = 0x1000;
+
+/// Remove `[start, start + bytes)` from `memmap`.  Failures are only
+/// logged.
+fn remove_memory(memmap: &mut heap::MemoryMap, start: u64, bytes: u64) {
+    if let Err(e) = memmap.remove(PhysAddr::new(start), bytes) {
+        write!(log(), "Too many memory areas: {}\n", e).unwrap();
+    }
+}
+
+/// Fill `memmap` with `areas`.  Areas may overlap, so unavailable areas
+/// are removed after all available areas are merged.
+fn build_memory_map<I>(
+    memmap: &mut heap::MemoryMap, areas: I, limit: Option<u64>)
+    where I: Iterator<Item = mb::MemoryArea> + Clone
+{
+    for mm in areas.clone() {
+        write!(log(), "{:?}\n", mm).unwrap();
+        if mm.is_available() {
+            add_memory(memmap, mm.start, mm.bytes, limit);
+        }
+    }
+    for mm in areas.filter(|mm| !mm.is_available()) {
+        remove_memory(memmap, mm.start, mm.bytes);
+    }
+}
+
+/// Mark data given by the bootloader as used.  Failures are only logged.
+fn reserve_boot_data(what: &str, adr: usize, bytes: usize) {
+    if let Err(e) = heap::reserve(PhysAddr::new(adr as u64), bytes) {
+        write!(log(), "Failed to reserve {}: {}\n", what, e).unwrap();
+    }
+}
+
+/// Mark a string given by the bootloader as used with the terminating NUL.
+fn reserve_boot_str(what: &str, s: &str) {
+    reserve_boot_data(what, s.as_ptr() as usize, s.len() + 1);
 }
 
 /// Detect multiboot2 protocol and load if succeeded.
 #[cfg(feature = "boot_multiboot2")]
 fn load_mb2(magic: u32, tag: *const u32) -> Result<(), Error> {
     if magic != MULTIBOOT2_BOOTLOADER_MAGIC {
-        return Err(Error::Fail);
+        return Err(Error::Unsupported);
     }
 
     let mb2_tags = unsafe { multiboot2::load(tag as usize) };
 
-    if let Some(mmap_tag) = mb2_tags.memory_map_tag() {
-        for mm in mmap_tag.memory_areas() {
-            write!(log(), "{:?}\n", mm).unwrap();
+    let info = unsafe { mb2::Info::load(tag as usize) };
+
+    let limit = mem_limit(
+        mb2_tags.command_line_tag().map(|tag| tag.command_line()));
+    let mut memmap = heap::MemoryMap::new();
+    build_memory_map(&mut memmap, info.memory_areas(), limit);
+    remove_memory(&mut memmap, 0, LOW_RESERVED_BYTES);
+    heap::add_memory_map(&memmap)
+        .map_err(|e| e.context("multiboot2 memory map"))?;
+
+    heap::reserve_loader()?;
+    reserve_boot_data("multiboot2 info",
+        mb2_tags.start_address(), mb2_tags.total_size());
+    for m in mb2_tags.module_tags() {
+        let bytes = m.end_address().saturating_sub(m.start_address());
+        reserve_boot_data(
+            "module", m.start_address() as usize, bytes as usize);
+    }
+    let _ = heap::grow_range_buf();
+
+    handoff::init()?;
+    handoff::add_memory_map(&memmap)?;
+    for mm in info.memory_areas().filter(|mm| !mm.is_available()) {
+        let kind = handoff::firmware_kind(mm.typ);
+        handoff::add_memory(mm.start, mm.bytes, kind)?;
+    }
+    for m in mb2_tags.module_tags() {
+        let bytes = m.end_address().saturating_sub(m.start_address());
+        handoff::add_module(m.start_address() as u64, bytes as u64, m.name())?;
+    }
+    if let Some(tag) = mb2_tags.command_line_tag() {
+        handoff::set_cmdline(tag.command_line())?;
+    }
+    if let Some(fb) = info.framebuffer() {
+        handoff::set_framebuffer(mb_framebuffer(&fb))?;
+    }
+    if let Some(rsdp) = info.rsdp() {
+        if let Err(e) = handoff::set_rsdp(rsdp) {
+            write!(log(), "ACPI RSDP tag is ignored: {}\n", e).unwrap();
         }
     }
 
@@ -39,29 +144,144 @@ fn load_mb2(magic: u32, tag: *const u32) -> Result<(), Error> {
 #[cfg(not(feature = "boot_multiboot2"))]
 const load_mb2: fn(u32, *const u32) -> Result<(), Error> = load_boot_none;
 
-const load_mb: fn(u32, *const u32) -> Result<(), Error> = load_boot_none;
+fn mb_framebuffer(fb: &mb::Framebuffer) -> bootinfo::Framebuffer {
+    let kind = match fb.typ {
+        0 => bootinfo::FramebufferKind::INDEXED,
+        1 => bootinfo::FramebufferKind::RGB,
+        2 => bootinfo::FramebufferKind::EGA_TEXT,
+        _ => bootinfo::FramebufferKind::NONE,
+    };
+    bootinfo::Framebuffer {
+        adr: fb.adr,
+        pitch: fb.pitch,
+        width: fb.width,
+        height: fb.height,
+        bpp: fb.bpp,
+        kind,
+        _reserved: 0,
+    }
+}
+
+/// Detect multiboot protocol and load if succeeded.
+fn load_mb(magic: u32, info: *const u32) -> Result<(), Error> {
+    if magic != mb::MULTIBOOT_BOOTLOADER_MAGIC {
+        return Err(Error::Unsupported);
+    }
+
+    let info = unsafe { mb::Info::load(info as usize) };
+
+    let limit = mem_limit(info.cmdline());
+    let mut memmap = heap::MemoryMap::new();
+    if info.memory_map_buf().is_some() {
+        build_memory_map(&mut memmap, info.memory_areas(), limit);
+    } else if let Some((lower, upper)) = info.basic_memory() {
+        // lower memory starts at 0 and upper memory starts at 1MiB.
+        add_memory(&mut memmap, 0, lower as u64 * 1024, limit);
+        add_memory(&mut memmap, 0x100000, upper as u64 * 1024, limit);
+    }
+    remove_memory(&mut memmap, 0, LOW_RESERVED_BYTES);
+    heap::add_memory_map(&memmap)
+        .map_err(|e| e.context("multiboot memory map"))?;
+
+    // everything given by the bootloader must survive until the kernel
+    // takes over.
+    heap::reserve_loader()?;
+    reserve_boot_data(
+        "multiboot info", info.start_address(), info.total_size());
+    if let Some((adr, bytes)) = info.memory_map_buf() {
+        reserve_boot_data("memory map", adr, bytes);
+    }
+    if let Some((adr, bytes)) = info.module_list_buf() {
+        reserve_boot_data("module list", adr, bytes);
+    }
+    for m in info.modules() {
+        write!(log(), "{:?}\n", m).unwrap();
+        let bytes = m.end.saturating_sub(m.start) as usize;
+        reserve_boot_data("module", m.start as usize, bytes);
+        if let Some(cmdline) = m.cmdline {
+            reserve_boot_str("module cmdline", cmdline);
+        }
+    }
+    if let Some(cmdline) = info.cmdline() {
+        write!(log(), "cmdline: {}\n", cmdline).unwrap();
+        reserve_boot_str("cmdline", cmdline);
+    }
+    if let Some(name) = info.boot_loader_name() {
+        write!(log(), "boot loader: {}\n", name).unwrap();
+        reserve_boot_str("boot loader name", name);
+    }
+    if let Some(fb) = info.framebuffer() {
+        write!(log(), "{:?}\n", fb).unwrap();
+    }
+    let _ = heap::grow_range_buf();
+
+    handoff::init()?;
+    handoff::add_memory_map(&memmap)?;
+    for mm in info.memory_areas().filter(|mm| !mm.is_available()) {
+        let kind = handoff::firmware_kind(mm.typ);
+        handoff::add_memory(mm.start, mm.bytes, kind)?;
+    }
+    for m in info.modules() {
+        let bytes = m.end.saturating_sub(m.start);
+        let cmdline = m.cmdline.unwrap_or("");
+        handoff::add_module(m.start as u64, bytes as u64, cmdline)?;
+    }
+    if let Some(cmdline) = info.cmdline() {
+        handoff::set_cmdline(cmdline)?;
+    }
+    if let Some(fb) = info.framebuffer() {
+        handoff::set_framebuffer(mb_framebuffer(&fb))?;
+    }
+
+    Ok(())
+}
 
+/// Each loader returns Unsupported if the magic is not its own.
 fn load_bootprotocol(magic: u32, tag: *const u32) -> Result<(), Error> {
-    if cfg!(feature = "boot_multiboot2") && load_mb2(magic, tag).is_ok() {
-        return Ok(());
-    } else if load_mb(magic, tag).is_ok() {
-        return Ok(());
+    match load_mb2(magic, tag) {
+        Err(e) if e.code() == Code::Unsupported => {},
+        r => return r,
     }
-    Err(Error::Fail)
+    load_mb(magic, tag)
 }
 
+/// The first module is the ELF64 kernel.
+fn load_kernel() -> Result<elf::Image, Error> {
+    match handoff::module(0)? {
+        Some((start, bytes)) => elf::load(start, bytes),
+        None => Err(Error::NotFound.context("kernel module")),
+    }
+}
+
+/// Returns only if failed.
 #[no_mangle]
 pub extern "C" fn load(magic: u32, tag: *const u32) -> u32 {
     heap::init();
 
-    let r = load_bootprotocol(magic, tag);
+    let r = long_mode::check_cpu()
+        .and_then(|()| load_bootprotocol(magic, tag))
+        .and_then(|()| long_mode::prepare(&load_kernel()?));
     match r {
-        Ok(()) => {
-            0
+        Ok(kernel) => {
+            heap::dump();
+            match handoff::finish() {
+                Ok(adr) => {
+                    write!(log(), "Boot info: {:#x}\n", adr.get()).unwrap();
+                    kernel.enter(adr)
+                },
+                Err(e) => {
+                    write!(log(), "Boot info failed: {}\n", e).unwrap();
+                    e.code() as u32
+                },
+            }
+        },
+        Err(Error::Unsupported) => {
+            write!(log(), "No boot protocols detected.\n").unwrap();
+            Error::Unsupported.code() as u32
         },
         Err(e) => {
-            write!(log(), "No boot protocols detected.");
-            1
+            write!(log(), "Boot failed: {}\n", e).unwrap();
+            e.code() as u32
         },
     }
 }
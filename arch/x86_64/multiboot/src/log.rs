@@ -5,6 +5,8 @@
 
 use core::fmt;
 
+use util::spinlock::{IrqSpinLock, IrqSpinLockGuard};
+
 /// Display logs and messages in VGA.
 pub struct TextVGA {
     xpos:   i32,
@@ -43,14 +45,32 @@ impl TextVGA
     }
 }
 
+const MEMLOG_BYTES: usize = 0x1000;
+
 /// On memory logs for passing to the kernel.
+/// Logs over the buffer are dropped.
 struct MemLog {
+    buf: [u8; MEMLOG_BYTES],
+    len: usize,
+}
+
+impl MemLog {
+    fn puts(&mut self, s: &str) {
+        let n = s.len().min(MEMLOG_BYTES - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+    }
 }
 
 pub struct Logger {
     textvga: TextVGA,
+    memlog: MemLog,
 }
-static mut logger: Logger = Logger {
+
+// VRAM is accessed only through LOGGER.
+unsafe impl Send for Logger {}
+
+static LOGGER: IrqSpinLock<Logger> = IrqSpinLock::new(Logger {
     textvga: TextVGA {
         xpos: 0,
         ypos: 0,
@@ -59,15 +79,28 @@ static mut logger: Logger = Logger {
         height: 25,
         vram: 0xb8000 as *mut u8,
     },
-};
+    memlog: MemLog {
+        buf: [0; MEMLOG_BYTES],
+        len: 0,
+    },
+});
 
 impl fmt::Write for Logger {
     fn write_str(&mut self, s: &str) -> fmt::Result {
         self.textvga.puts(s);
+        self.memlog.puts(s);
         Ok(())
     }
 }
 
-pub fn log() -> &'static mut Logger {
-    unsafe { &mut logger }
+/// The logger is locked until the guard is dropped, so do not call log()
+/// again while formatting its arguments.
+pub fn log() -> IrqSpinLockGuard<'static, Logger> {
+    LOGGER.lock()
+}
+
+/// (address, bytes) of the logs on memory.
+pub fn mem_log() -> (usize, usize) {
+    let logger = LOGGER.lock();
+    (logger.memlog.buf.as_ptr() as usize, logger.memlog.len)
 }
@@ -0,0 +1,215 @@
+// Uniqos  --  Unique Operating System
+// (c) 2019 KATO Takeshi
+// Released under the MIT license
+
+/// Transition to the 64-bit kernel.
+///
+/// The page table maps physical memory at 0 and at PHYS_MAP_BASE with 2MiB
+/// pages.  Both PML4 entries share the same PDPT.  Kernel segments linked
+/// at other addresses are mapped with 4KiB pages.
+
+use core::alloc::Layout;
+use core::arch::x86::{__cpuid, has_cpuid};
+use core::fmt::Write;
+use core::ptr;
+
+use util::error::Error;
+use util::ops::{self, PhysAddr, VirtAddr};
+
+use super::elf;
+use super::handoff;
+use super::heap;
+use super::log::log;
+
+
+pub const PHYS_MAP_BASE: u64 = 0xffff_8000_0000_0000;
+/// At least 4GiB is mapped for MMIO such as framebuffers.
+const PHYS_MAP_MIN: u64 = 0x1_0000_0000;
+const KERNEL_STACK_BYTES: usize = 0x10000;
+
+const PTE_PRESENT: u64 = 1 << 0;
+const PTE_WRITABLE: u64 = 1 << 1;
+const PTE_HUGE: u64 = 1 << 7;
+const PTE_ADR_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+const ENTRIES: usize = 512;
+const PAGE_2M: u64 = 1 << 21;
+const PAGE_1G: u64 = 1 << 30;
+
+extern "C" {
+    fn enter_long_mode(
+        pml4: u32, bootinfo: u32,
+        entry_low: u32, entry_high: u32,
+        stack_low: u32, stack_high: u32) -> !;
+}
+
+/// Fail unless the CPU supports long mode.
+// __cpuid is safe only on newer toolchains.
+#[allow(unused_unsafe)]
+pub fn check_cpu() -> Result<(), Error> {
+    if !has_cpuid() {
+        return Err(Error::Unsupported.context("CPUID"));
+    }
+    let (pae, lm) = unsafe {
+        (__cpuid(1).edx & (1 << 6) != 0,
+         __cpuid(0x8000_0000).eax >= 0x8000_0001 &&
+            __cpuid(0x8000_0001).edx & (1 << 29) != 0)
+    };
+    if pae && lm {
+        Ok(())
+    } else {
+        Err(Error::Unsupported.context("long mode"))
+    }
+}
+
+/// Allocate contiguous `num` tables filled with 0.
+fn alloc_tables(num: usize) -> Result<*mut u64, Error> {
+    let layout = Layout::from_size_align(num * ops::PAGE_SIZE, ops::PAGE_SIZE)
+        .map_err(|_| Error::Inval)?;
+    let p = handoff::alloc(layout)? as *mut u64;
+    unsafe { ptr::write_bytes(p, 0, num * ENTRIES) };
+    Ok(p)
+}
+
+fn phys_of(p: *const u64) -> u64 {
+    heap::virt_to_phys(VirtAddr::new(p as usize)).get()
+}
+
+/// Index of the table at `level`.  PML4 is level 4.
+fn index(adr: u64, level: u32) -> usize {
+    (adr >> (ops::PAGE_SHIFT + 9 * (level - 1))) as usize & (ENTRIES - 1)
+}
+
+fn pml4_index(adr: u64) -> usize {
+    index(adr, 4)
+}
+
+/// The table pointed by `table[i]`.  It is allocated if not present.
+fn next_table(table: *mut u64, i: usize) -> Result<*mut u64, Error> {
+    let ent = unsafe { *table.add(i) };
+    if ent & PTE_PRESENT == 0 {
+        let next = alloc_tables(1)?;
+        unsafe { *table.add(i) = phys_of(next) | PTE_PRESENT | PTE_WRITABLE };
+        return Ok(next);
+    }
+    if ent & PTE_HUGE != 0 {
+        return Err(Error::Exist.context("mapped by a huge page"));
+    }
+    let next = heap::phys_to_virt(PhysAddr::new(ent & PTE_ADR_MASK))
+        .ok_or(Error::Range)?;
+    Ok(next.get() as *mut u64)
+}
+
+pub struct PageTable {
+    pml4: *mut u64,
+}
+
+impl PageTable {
+    pub fn new() -> Result<Self, Error> {
+        Ok(PageTable { pml4: alloc_tables(1)? })
+    }
+
+    pub fn pml4(&self) -> PhysAddr {
+        PhysAddr::new(phys_of(self.pml4))
+    }
+
+    /// Map physical [0, bytes) at 0 and PHYS_MAP_BASE.  `bytes` is rounded
+    /// up to 1GiB and limited to 512GiB.
+    pub fn map_phys(&mut self, bytes: u64) -> Result<(), Error> {
+        let gbs = bytes / PAGE_1G + (bytes % PAGE_1G != 0) as u64;
+        let gbs = gbs.min(ENTRIES as u64) as usize;
+        let pdpt = alloc_tables(1)?;
+        let pds = alloc_tables(gbs)?;
+        for i in 0..gbs {
+            let pd = unsafe { pds.add(i * ENTRIES) };
+            for j in 0..ENTRIES {
+                let adr = i as u64 * PAGE_1G + j as u64 * PAGE_2M;
+                unsafe {
+                    *pd.add(j) = adr | PTE_PRESENT | PTE_WRITABLE | PTE_HUGE;
+                }
+            }
+            unsafe { *pdpt.add(i) = phys_of(pd) | PTE_PRESENT | PTE_WRITABLE };
+        }
+
+        let ent = phys_of(pdpt) | PTE_PRESENT | PTE_WRITABLE;
+        unsafe {
+            *self.pml4.add(pml4_index(0)) = ent;
+            *self.pml4.add(pml4_index(PHYS_MAP_BASE)) = ent;
+        }
+        Ok(())
+    }
+
+    /// Map `[phys, phys + bytes)` at `virt` with 4KiB pages.  `virt` and
+    /// `phys` must have the same offset in a page.
+    pub fn map(&mut self, virt: u64, phys: PhysAddr, bytes: u64)
+        -> Result<(), Error>
+    {
+        let page = ops::PAGE_SIZE as u64;
+        let offset = virt % page;
+        if phys.get() % page != offset {
+            return Err(Error::Inval);
+        }
+        let pages = (offset + bytes + page - 1) / page;
+        let (virt, phys) = (virt - offset, phys.get() - offset);
+        for i in 0..pages {
+            let adr = virt + i * page;
+            let mut table = self.pml4;
+            for level in (2..=4).rev() {
+                table = next_table(table, index(adr, level))?;
+            }
+            unsafe {
+                *table.add(index(adr, 1)) =
+                    (phys + i * page) | PTE_PRESENT | PTE_WRITABLE;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Ready to enter the kernel.
+pub struct Kernel {
+    page_table: PageTable,
+    entry: u64,
+    stack_top: u64,
+}
+
+/// True if `virt` is in the 1:1 map or the physical memory map.
+fn is_phys_mapped(virt: u64, phys: PhysAddr, phys_end: u64) -> bool {
+    phys.get() < phys_end &&
+        (virt == phys.get() || virt == PHYS_MAP_BASE + phys.get())
+}
+
+/// Build the page table and the stack for the kernel `image`.  They are
+/// recorded in BootInfo, so BootInfo must be allocated.
+pub fn prepare(image: &elf::Image) -> Result<Kernel, Error> {
+    let mut page_table = PageTable::new()?;
+    let phys_end = handoff::memory_end()?.max(PHYS_MAP_MIN);
+    page_table.map_phys(phys_end)?;
+    for seg in image.segments.iter() {
+        if !is_phys_mapped(seg.virt, seg.phys, phys_end) {
+            page_table.map(seg.virt, seg.phys, seg.bytes)
+                .map_err(|e| e.context("kernel segment"))?;
+        }
+    }
+    handoff::set_paging(page_table.pml4(), PHYS_MAP_BASE)?;
+
+    let layout = Layout::from_size_align(KERNEL_STACK_BYTES, ops::PAGE_SIZE)
+        .map_err(|_| Error::Inval)?;
+    let stack = handoff::alloc(layout)? as *const u64;
+    let stack_top = PHYS_MAP_BASE + phys_of(stack) + KERNEL_STACK_BYTES as u64;
+
+    Ok(Kernel { page_table, entry: image.entry, stack_top })
+}
+
+impl Kernel {
+    /// The kernel entry gets `bootinfo` as the first argument.
+    pub fn enter(self, bootinfo: PhysAddr) -> ! {
+        writeln!(log(), "Enter kernel: {:#x}", self.entry).unwrap();
+        unsafe {
+            enter_long_mode(
+                self.page_table.pml4().get() as u32, bootinfo.get() as u32,
+                self.entry as u32, (self.entry >> 32) as u32,
+                self.stack_top as u32, (self.stack_top >> 32) as u32)
+        }
+    }
+}
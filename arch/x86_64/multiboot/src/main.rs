@@ -1,8 +1,12 @@
 
+#![feature(alloc_error_handler)]
 #![feature(lang_items)]
 #![no_std]
 #![no_main]
 
+extern crate alloc;
+
+use core::alloc::Layout;
 use core::panic::PanicInfo;
 #[panic_handler]
 fn panic(_info: &PanicInfo) -> ! {
@@ -12,10 +16,21 @@ fn panic(_info: &PanicInfo) -> ! {
 #[lang = "eh_personality"]
 extern fn eh_personality() {}
 
+#[alloc_error_handler]
+fn alloc_error(_layout: Layout) -> ! {
+    loop {}
+}
+
 
+mod elf;
+mod handoff;
 mod heap;
 mod load;
 mod log;
+mod long_mode;
+mod mb;
+#[cfg(feature = "boot_multiboot2")]
+mod mb2;
 
 //#[no_mangle]
 //pub extern "C" fn _start() -> ! {
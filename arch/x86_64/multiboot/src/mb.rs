@@ -0,0 +1,273 @@
+// Uniqos  --  Unique Operating System
+// (c) 2019 KATO Takeshi
+// Released under the MIT license
+
+/// Multiboot (v1) information structure.
+
+use core::ffi::CStr;
+use core::fmt;
+use core::mem::size_of;
+use core::ptr;
+
+
+pub const MULTIBOOT_BOOTLOADER_MAGIC: u32 = 0x2badb002;
+
+// Bits of Info::flags.
+const INFO_MEMORY: u32 = 1 << 0;
+const INFO_CMDLINE: u32 = 1 << 2;
+const INFO_MODS: u32 = 1 << 3;
+const INFO_MEM_MAP: u32 = 1 << 6;
+const INFO_BOOT_LOADER_NAME: u32 = 1 << 9;
+const INFO_FRAMEBUFFER: u32 = 1 << 12;
+
+/// Type of available memory in the memory map.
+pub const MEMORY_AVAILABLE: u32 = 1;
+
+#[repr(C)]
+#[allow(dead_code)]
+struct RawInfo {
+    flags: u32,
+    mem_lower: u32,
+    mem_upper: u32,
+    boot_device: u32,
+    cmdline: u32,
+    mods_count: u32,
+    mods_addr: u32,
+    syms: [u32; 4],
+    mmap_length: u32,
+    mmap_addr: u32,
+    drives_length: u32,
+    drives_addr: u32,
+    config_table: u32,
+    boot_loader_name: u32,
+    apm_table: u32,
+    vbe_control_info: u32,
+    vbe_mode_info: u32,
+    vbe_mode: u16,
+    vbe_interface_seg: u16,
+    vbe_interface_off: u16,
+    vbe_interface_len: u16,
+    framebuffer_addr_low: u32,
+    framebuffer_addr_high: u32,
+    framebuffer_pitch: u32,
+    framebuffer_width: u32,
+    framebuffer_height: u32,
+    framebuffer_bpp: u8,
+    framebuffer_type: u8,
+    color_info: [u8; 6],
+}
+
+/// Entries are not aligned.  `size` does not include itself.
+#[repr(C, packed)]
+struct RawMmapEntry {
+    size: u32,
+    addr: u64,
+    len: u64,
+    typ: u32,
+}
+
+#[repr(C)]
+struct RawModule {
+    mod_start: u32,
+    mod_end: u32,
+    cmdline: u32,
+    _pad: u32,
+}
+
+/// String given by the bootloader.  None if it is not UTF-8.
+unsafe fn c_str(adr: u32) -> Option<&'static str> {
+    if adr == 0 {
+        return None;
+    }
+    CStr::from_ptr(adr as usize as *const _).to_str().ok()
+}
+
+/// The information structure given in %ebx.
+pub struct Info {
+    raw: &'static RawInfo,
+}
+
+impl Info {
+    /// `adr` must point to the structure given by the bootloader.
+    pub unsafe fn load(adr: usize) -> Self {
+        Info {
+            raw: &*(adr as *const RawInfo),
+        }
+    }
+
+    fn has(&self, flag: u32) -> bool {
+        self.raw.flags & flag != 0
+    }
+
+    pub fn start_address(&self) -> usize {
+        self.raw as *const RawInfo as usize
+    }
+
+    pub fn total_size(&self) -> usize {
+        size_of::<RawInfo>()
+    }
+
+    /// (lower, upper) memory in KiB.  Lower memory starts at 0 and upper
+    /// memory starts at 1MiB.
+    pub fn basic_memory(&self) -> Option<(u32, u32)> {
+        if self.has(INFO_MEMORY) {
+            Some((self.raw.mem_lower, self.raw.mem_upper))
+        } else {
+            None
+        }
+    }
+
+    pub fn cmdline(&self) -> Option<&'static str> {
+        if self.has(INFO_CMDLINE) {
+            unsafe { c_str(self.raw.cmdline) }
+        } else {
+            None
+        }
+    }
+
+    pub fn boot_loader_name(&self) -> Option<&'static str> {
+        if self.has(INFO_BOOT_LOADER_NAME) {
+            unsafe { c_str(self.raw.boot_loader_name) }
+        } else {
+            None
+        }
+    }
+
+    /// (address, bytes) of the memory map buffer.
+    pub fn memory_map_buf(&self) -> Option<(usize, usize)> {
+        if self.has(INFO_MEM_MAP) {
+            Some((self.raw.mmap_addr as usize, self.raw.mmap_length as usize))
+        } else {
+            None
+        }
+    }
+
+    /// All areas includes reserved ones.
+    pub fn memory_areas(&self) -> MemoryAreaIter {
+        let (cur, end) = match self.memory_map_buf() {
+            Some((adr, bytes)) => (adr, adr + bytes),
+            None => (0, 0),
+        };
+        MemoryAreaIter { cur, end }
+    }
+
+    /// (address, bytes) of the module list.
+    pub fn module_list_buf(&self) -> Option<(usize, usize)> {
+        if self.has(INFO_MODS) {
+            let bytes = self.raw.mods_count as usize * size_of::<RawModule>();
+            Some((self.raw.mods_addr as usize, bytes))
+        } else {
+            None
+        }
+    }
+
+    pub fn modules(&self) -> ModuleIter {
+        let (cur, end) = match self.module_list_buf() {
+            Some((adr, bytes)) => (adr, adr + bytes),
+            None => (0, 0),
+        };
+        ModuleIter { cur, end }
+    }
+
+    pub fn framebuffer(&self) -> Option<Framebuffer> {
+        if !self.has(INFO_FRAMEBUFFER) {
+            return None;
+        }
+        let raw = self.raw;
+        Some(Framebuffer {
+            adr: (raw.framebuffer_addr_high as u64) << 32 |
+                 raw.framebuffer_addr_low as u64,
+            pitch: raw.framebuffer_pitch,
+            width: raw.framebuffer_width,
+            height: raw.framebuffer_height,
+            bpp: raw.framebuffer_bpp,
+            typ: raw.framebuffer_type,
+        })
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct MemoryArea {
+    pub start: u64,
+    pub bytes: u64,
+    pub typ: u32,
+}
+
+impl MemoryArea {
+    pub fn is_available(&self) -> bool {
+        self.typ == MEMORY_AVAILABLE
+    }
+}
+
+impl fmt::Debug for MemoryArea {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "MemoryArea {{ start: {:#x}, bytes: {:#x}, typ: {} }}",
+            self.start, self.bytes, self.typ)
+    }
+}
+
+#[derive(Clone)]
+pub struct MemoryAreaIter {
+    cur: usize,
+    end: usize,
+}
+
+impl Iterator for MemoryAreaIter {
+    type Item = MemoryArea;
+
+    fn next(&mut self) -> Option<MemoryArea> {
+        if self.cur + size_of::<RawMmapEntry>() > self.end {
+            return None;
+        }
+        let ent = unsafe {
+            ptr::read_unaligned(self.cur as *const RawMmapEntry)
+        };
+        self.cur += ent.size as usize + size_of::<u32>();
+        Some(MemoryArea {
+            start: ent.addr,
+            bytes: ent.len,
+            typ: ent.typ,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Module {
+    pub start: u32,
+    /// Exclusive.
+    pub end: u32,
+    pub cmdline: Option<&'static str>,
+}
+
+pub struct ModuleIter {
+    cur: usize,
+    end: usize,
+}
+
+impl Iterator for ModuleIter {
+    type Item = Module;
+
+    fn next(&mut self) -> Option<Module> {
+        if self.cur >= self.end {
+            return None;
+        }
+        let raw = unsafe { &*(self.cur as *const RawModule) };
+        self.cur += size_of::<RawModule>();
+        Some(Module {
+            start: raw.mod_start,
+            end: raw.mod_end,
+            cmdline: unsafe { c_str(raw.cmdline) },
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Framebuffer {
+    pub adr: u64,
+    pub pitch: u32,
+    pub width: u32,
+    pub height: u32,
+    pub bpp: u8,
+    /// 0: indexed color, 1: direct RGB, 2: EGA text.
+    pub typ: u8,
+}
@@ -0,0 +1,176 @@
+// Uniqos  --  Unique Operating System
+// (c) 2019 KATO Takeshi
+// Released under the MIT license
+
+/// Multiboot2 tags which the multiboot2 crate does not expose.
+///
+/// The crate gives only available memory areas, so the memory map tag is
+/// read here to get reserved, ACPI and bad areas too.  The framebuffer and
+/// ACPI RSDP tags are read here as well.
+
+use core::mem::size_of;
+use core::ptr;
+use core::slice;
+
+use super::mb::{Framebuffer, MemoryArea};
+
+
+const TAG_END: u32 = 0;
+const TAG_MEMORY_MAP: u32 = 6;
+const TAG_FRAMEBUFFER: u32 = 8;
+const TAG_ACPI_OLD: u32 = 14;
+const TAG_ACPI_NEW: u32 = 15;
+
+/// Tags follow the fixed part and are aligned to 8 bytes.
+#[repr(C)]
+struct RawInfo {
+    total_size: u32,
+    _reserved: u32,
+}
+
+#[repr(C)]
+struct RawTag {
+    typ: u32,
+    size: u32,
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+struct RawMemoryMapTag {
+    tag: RawTag,
+    entry_size: u32,
+    entry_version: u32,
+}
+
+#[repr(C)]
+struct RawMemoryMapEntry {
+    base_addr: u64,
+    length: u64,
+    typ: u32,
+    _reserved: u32,
+}
+
+/// The color info follows, which depends on `framebuffer_type`.
+#[repr(C)]
+#[allow(dead_code)]
+struct RawFramebufferTag {
+    tag: RawTag,
+    framebuffer_addr: u64,
+    framebuffer_pitch: u32,
+    framebuffer_width: u32,
+    framebuffer_height: u32,
+    framebuffer_bpp: u8,
+    framebuffer_type: u8,
+    _reserved: u16,
+}
+
+/// The information structure given in %ebx.
+pub struct Info {
+    start: usize,
+    end: usize,
+}
+
+impl Info {
+    /// `adr` must point to the structure given by the bootloader.
+    pub unsafe fn load(adr: usize) -> Self {
+        let raw = &*(adr as *const RawInfo);
+        Info {
+            start: adr,
+            end: adr + raw.total_size as usize,
+        }
+    }
+
+    /// (address, bytes) of the first tag of `typ`.
+    fn find(&self, typ: u32) -> Option<(usize, usize)> {
+        let mut cur = self.start + size_of::<RawInfo>();
+        while cur + size_of::<RawTag>() <= self.end {
+            let tag = unsafe { &*(cur as *const RawTag) };
+            if tag.typ == TAG_END || (tag.size as usize) < size_of::<RawTag>()
+            {
+                break;
+            }
+            if tag.typ == typ {
+                return Some((cur, tag.size as usize));
+            }
+            cur += (tag.size as usize + 7) & !7;
+        }
+        None
+    }
+
+    /// All areas includes reserved ones.
+    pub fn memory_areas(&self) -> MemoryAreaIter {
+        let empty = MemoryAreaIter { cur: 0, end: 0, entry_size: 0 };
+        let (adr, bytes) = match self.find(TAG_MEMORY_MAP) {
+            Some(tag) => tag,
+            None => return empty,
+        };
+        if bytes < size_of::<RawMemoryMapTag>() {
+            return empty;
+        }
+        let tag = unsafe { &*(adr as *const RawMemoryMapTag) };
+        let entry_size = tag.entry_size as usize;
+        if entry_size < size_of::<RawMemoryMapEntry>() {
+            return empty;
+        }
+        MemoryAreaIter {
+            cur: adr + size_of::<RawMemoryMapTag>(),
+            end: adr + bytes,
+            entry_size,
+        }
+    }
+}
+
+impl Info {
+    pub fn framebuffer(&self) -> Option<Framebuffer> {
+        let (adr, bytes) = self.find(TAG_FRAMEBUFFER)?;
+        if bytes < size_of::<RawFramebufferTag>() {
+            return None;
+        }
+        let raw = unsafe {
+            ptr::read_unaligned(adr as *const RawFramebufferTag)
+        };
+        Some(Framebuffer {
+            adr: raw.framebuffer_addr,
+            pitch: raw.framebuffer_pitch,
+            width: raw.framebuffer_width,
+            height: raw.framebuffer_height,
+            bpp: raw.framebuffer_bpp,
+            typ: raw.framebuffer_type,
+        })
+    }
+
+    /// The copy of the RSDP.  ACPI 2.0 one is preferred.
+    pub fn rsdp(&self) -> Option<&[u8]> {
+        let (adr, bytes) = self.find(TAG_ACPI_NEW)
+            .or_else(|| self.find(TAG_ACPI_OLD))?;
+        let data = adr + size_of::<RawTag>();
+        let len = bytes - size_of::<RawTag>();
+        Some(unsafe { slice::from_raw_parts(data as *const u8, len) })
+    }
+}
+
+#[derive(Clone)]
+pub struct MemoryAreaIter {
+    cur: usize,
+    end: usize,
+    entry_size: usize,
+}
+
+impl Iterator for MemoryAreaIter {
+    type Item = MemoryArea;
+
+    fn next(&mut self) -> Option<MemoryArea> {
+        if self.cur + size_of::<RawMemoryMapEntry>() > self.end {
+            return None;
+        }
+        let ent = unsafe {
+            ptr::read_unaligned(self.cur as *const RawMemoryMapEntry)
+        };
+        self.cur += self.entry_size;
+        Some(MemoryArea {
+            start: ent.base_addr,
+            bytes: ent.length,
+            typ: ent.typ,
+        })
+    }
+}
@@ -1,6 +1,7 @@
 
 #![no_std]
 
+use bootinfo::BootInfo;
 use core::fmt::Write;
 mod textvga;
 use self::textvga::TextVGA;
@@ -12,9 +13,14 @@ fn panic(_info: &PanicInfo) -> ! {
     loop {}
 }
 
+/// Entered from the loader in long mode.  `bootinfo` is mapped 1:1.
 #[no_mangle]
-pub extern "C" fn _start() -> ! {
+pub extern "C" fn _start(bootinfo: &'static BootInfo) -> ! {
     let mut vga = TextVGA::new(80, 25, 0xb8000);
+    if !bootinfo.is_valid() {
+        writeln!(&mut vga, "Boot info mismatch.").unwrap();
+        loop {}
+    }
     vga.putc(b'a');
     //write!(&mut vga, "hello!").unwrap();
     write!(&mut vga, "").unwrap();
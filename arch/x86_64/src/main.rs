@@ -0,0 +1,4 @@
+#![no_std]
+#![no_main]
+
+extern crate x86_64;
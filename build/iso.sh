@@ -17,10 +17,17 @@ fi
 
 #KERNEL=target/i686-uniqos/debug/multiboot
 
-rm -f target/iso/kernel
+rm -f target/iso/kernel target/iso/uniqos
 mkdir -p target/iso/boot/grub
 cp $MBKERNEL target/iso/kernel
 
+# The first module is the 64-bit kernel loaded by the multiboot loader.
+MODULE='#module2 --nounzip (cd)/uniqos uniqos'
+if [ -f "$KERNEL64" ]; then
+    cp $KERNEL64 target/iso/uniqos
+    MODULE='module2 --nounzip (cd)/uniqos uniqos'
+fi
+
 cat > target/iso/boot/grub/grub.cfg <<CFG
 default='Uniqos'
 timeout=10
@@ -28,7 +35,7 @@ menuentry 'Uniqos' {
 set root=(cd)
 multiboot2 (cd)/kernel root=cd0
 #multiboot (cd)/kernel
-#module2 --nounzip (cd)/test
+$MODULE
 }
 CFG
 
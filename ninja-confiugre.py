@@ -29,17 +29,27 @@ x.recurse('util')
 
 if x.opt('boot_multiboot2'):
     mb_kernel = x.outroot('i686-uniqos', x.buildmode(), 'multiboot')
+    kernel64 = x.outroot('x86_64-uniqos', x.buildmode(), 'kernel64')
     x.recurse('arch/x86_64/multiboot')
     x.rule('mb_iso',
         'GRUB2_MOD_PATH={} '
 	'GRUB2_MKIMAGE={} '
 	'MBKERNEL=$kernel '
+	'KERNEL64=$kernel64 '
 	'build/iso.sh'.format(
         x.opt('GRUB2_MOD_PATH'),
         x.opt('GRUB2_MKIMAGE')))
     x.build('target/uniqos.iso', 'mb_iso',
-        implicit = mb_kernel,
-        variables = {'kernel': mb_kernel}
+        implicit = [mb_kernel, kernel64],
+        variables = {'kernel': mb_kernel, 'kernel64': kernel64}
+    )
+
+    # Boot the loader by multiboot v1 without the ISO.  The kernel is given
+    # as the first module.
+    x.build('run_mb', 'qemu',
+        implicit = [mb_kernel, kernel64],
+        variables = {
+            'opts': '-kernel ' + mb_kernel + ' -initrd ' + kernel64}
     )
 
 x.default(x.opt('DEFAULT_TARGET'))
@@ -4,22 +4,138 @@
 
 /// Original heap allocation implement instead of alloc::boxed::Box.
 
+use core::mem::{self, MaybeUninit};
 use core::ops;
+use core::ptr::{self, NonNull};
 
 
-pub struct X<T: ?Sized>(*mut T);
+/// Slot number for the owner which is not known which slot is used.
+pub const ANY_SLOT: usize = usize::MAX;
 
-impl<T> ops::Deref for X<T> {
+/// Allocator which takes back the memory of X.
+pub trait Dealloc {
+    /// Free `adr` allocated from `slot`.
+    fn dealloc_x(&mut self, slot: usize, adr: usize);
+}
+
+static mut DEFAULT_DEALLOC: Option<NonNull<dyn Dealloc>> = None;
+
+/// Set the owner of X made by `X::from_raw()`.
+///
+/// # Safety
+///
+/// `owner` must live while any X refers it.
+pub unsafe fn set_default_dealloc(owner: *mut dyn Dealloc) {
+    DEFAULT_DEALLOC = NonNull::new(owner);
+}
+
+/// Owning pointer.  The value is dropped and the memory is returned to the
+/// owner when X goes out of scope.
+pub struct X<T: ?Sized> {
+    ptr: NonNull<T>,
+    owner: Option<NonNull<dyn Dealloc>>,
+    slot: usize,
+}
+
+impl<T: ?Sized> X<T> {
+    /// Take the ownership of `ptr` allocated from `slot` of `owner`.
+    /// If `owner` is None, the memory is not freed.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be non-null and point to a valid `T`, and `owner` must
+    /// live while the X lives.
+    pub unsafe fn from_raw_in(
+        ptr: *mut T,
+        owner: Option<*mut dyn Dealloc>,
+        slot: usize) -> Self
+    {
+        Self {
+            ptr: NonNull::new_unchecked(ptr),
+            owner: owner.and_then(NonNull::new),
+            slot,
+        }
+    }
+
+    /// Take the ownership of `ptr` allocated from the default owner.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be non-null and point to a valid `T` allocated from the
+    /// default owner, e.g. returned by `X::into_raw()` of X which has the
+    /// default owner.
+    pub unsafe fn from_raw(ptr: *mut T) -> Self {
+        Self {
+            ptr: NonNull::new_unchecked(ptr),
+            owner: DEFAULT_DEALLOC,
+            slot: ANY_SLOT,
+        }
+    }
+
+    /// Give up the ownership.  The memory stays allocated.
+    pub fn into_raw(x: Self) -> *mut T {
+        let ptr = x.ptr.as_ptr();
+        mem::forget(x);
+        ptr
+    }
+
+    /// True if `x` is owned by the default owner, so `X::from_raw()` can
+    /// make it again from the raw pointer.
+    pub fn has_default_owner(x: &Self) -> bool {
+        let owner = x.owner.map(|p| p.as_ptr() as *mut u8);
+        owner == unsafe { DEFAULT_DEALLOC }.map(|p| p.as_ptr() as *mut u8)
+    }
+
+    pub fn leak<'a>(x: Self) -> &'a mut T
+        where T: 'a
+    {
+        unsafe { &mut *X::into_raw(x) }
+    }
+
+    pub fn as_ptr(&self) -> *mut T {
+        self.ptr.as_ptr()
+    }
+}
+
+impl<T> X<MaybeUninit<T>> {
+    /// Initialize the memory with `value`.
+    pub fn write(x: Self, value: T) -> X<T> {
+        let owner = x.owner;
+        let slot = x.slot;
+        let ptr = X::into_raw(x) as *mut T;
+        unsafe {
+            ptr::write(ptr, value);
+            X {
+                ptr: NonNull::new_unchecked(ptr),
+                owner,
+                slot,
+            }
+        }
+    }
+}
+
+impl<T: ?Sized> Drop for X<T> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(self.ptr.as_ptr());
+            if let Some(mut owner) = self.owner {
+                owner.as_mut().dealloc_x(
+                    self.slot, self.ptr.as_ptr() as *mut u8 as usize);
+            }
+        }
+    }
+}
+
+impl<T: ?Sized> ops::Deref for X<T> {
     type Target = T;
 
     fn deref(&self) -> &T {
-        unsafe { &*self.0 }
+        unsafe { self.ptr.as_ref() }
     }
 }
 
-impl<T> ops::DerefMut for X<T> {
+impl<T: ?Sized> ops::DerefMut for X<T> {
     fn deref_mut(&mut self) -> &mut T {
-        unsafe { &mut *self.0 }
+        unsafe { self.ptr.as_mut() }
     }
 }
-
@@ -7,51 +7,46 @@
 //extern crate alloc;
 
 use core::alloc::Layout;
-use core::mem::{transmute, size_of};
+use core::fmt;
+use core::iter;
+use core::mem::{align_of, size_of, MaybeUninit};
+use core::ptr;
 
-use super::boxed::X;
+use super::boxed::{ANY_SLOT, Dealloc, X};
 use super::cheap_list::*;
 use super::error::Error;
-use super::ops;
+use super::ops::{self, VirtAddr};
+use super::zeroable::{self, Zeroable};
 
 
-const SLOT_NUM: usize = 8;
+/// Default number of slots.
+pub const SLOT_NUM: usize = 8;
 
-type SlotMask = u8;
+/// Default number of built-in AdrRange entries.
+pub const RANGE_NUM: usize = 256;
 
-// This code cannot to be compile...
-/*
-use core::marker::Sized;
-unsafe fn generate_with0<T: Sized>() -> T
-{
-    mem::transmute::<[u8; mem::size_of::<T>()], T>([0u8; mem::size_of::<T>()])
-}
-*/
-// So instead to use this:
-macro_rules! generate_with0 {
-    ($type:ty) => {
-        transmute::<[u8; size_of::<$type>()], $type>([0u8; size_of::<$type>()])
-    };
-}
+/// Bit i selects slot i.
+pub type SlotMask = usize;
 
 #[derive(Clone, Copy)]
 pub struct _SlotDefEnt {
     start: usize,
     end: usize,
 }
-pub struct SlotDefs {
-    defs: [_SlotDefEnt; SLOT_NUM],
+pub struct SlotDefs<const SLOTS: usize = SLOT_NUM> {
+    defs: [_SlotDefEnt; SLOTS],
 }
 
-impl SlotDefs {
-    pub fn new() -> Self {
+impl<const SLOTS: usize> SlotDefs<SLOTS> {
+    pub const fn new() -> Self {
         Self {
-            defs: [_SlotDefEnt { start: 0, end: 0 }; SLOT_NUM],
+            defs: [_SlotDefEnt { start: 0, end: 0 }; SLOTS],
         }
     }
-    pub fn set(&mut self, i: usize, start: usize, end: usize) {
-        self.defs[i].start = start;
-        self.defs[i].end = end;
+    /// `end` is inclusive.
+    pub fn set(&mut self, i: usize, start: VirtAddr, end: VirtAddr) {
+        self.defs[i].start = start.get();
+        self.defs[i].end = end.get();
     }
 }
 
@@ -61,6 +56,9 @@ struct AdrRange {
     bytes: usize,  // Unused if bytes == 0.
 }
 
+// SAFETY: both fields are integers, and a zeroed entry is an unused one.
+unsafe impl Zeroable for AdrRange {}
+
 impl AdrRange {
     fn new() -> Self {
         Self { adr: 0, bytes: 0 }
@@ -76,6 +74,7 @@ struct AdrSlot {
     end: usize,
     free_ranges: SingleForwardList::<AdrRange, RawRefer<ForwardEnt<AdrRange>>>,
     used_ranges: SingleForwardList::<AdrRange, RawRefer<ForwardEnt<AdrRange>>>,
+    forgotten_bytes: usize,  // Handed off by alloc(forget = true).
 }
 
 impl AdrSlot {
@@ -86,35 +85,109 @@ impl AdrSlot {
            SingleForwardList::<AdrRange, RawRefer<ForwardEnt<AdrRange>>>::new(),
           used_ranges:
            SingleForwardList::<AdrRange, RawRefer<ForwardEnt<AdrRange>>>::new(),
+          forgotten_bytes: 0,
         }
     }
 }
 
+// SAFETY: the bounds and forgotten_bytes are integers, and the lists are
+// empty when zeroed since their heads are null pointers.  A zeroed slot is
+// the same as AdrSlot::new().
+unsafe impl Zeroable for AdrSlot {}
+
+/// Iterate ranges without modification.
+fn iter_ranges<'a>(
+    list: &'a SingleForwardList::<AdrRange, RawRefer<ForwardEnt<AdrRange>>>)
+    -> impl Iterator<Item = &'a AdrRange>
+{
+    let mut cur = list.get_front();
+    iter::from_fn(move || {
+        let e = cur.take()?;
+        cur = list.get_next(e);
+        Some(e.ref_elem())
+    })
+}
+
+/// Statistics of a slot.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SlotStat {
+    /// free_bytes + used_bytes + forgotten_bytes.
+    pub total_bytes: usize,
+    pub free_bytes: usize,
+    pub used_bytes: usize,
+    pub forgotten_bytes: usize,
+    /// The largest free range.
+    pub largest_free: usize,
+    /// Number of free ranges.
+    pub free_fragments: usize,
+    /// Number of used ranges.
+    pub used_fragments: usize,
+}
+
 fn is_masked(i: usize, slotmask: SlotMask) -> bool {
     (1 << i) & slotmask != 0
 }
 
-pub struct CheapAlloc {
+/// Unlink the entry next to `prev`, or the front entry if `prev` is None.
+fn unlink_range(
+    list: &mut SingleForwardList::<AdrRange, RawRefer<ForwardEnt<AdrRange>>>,
+    prev: Option<&mut ForwardEnt<AdrRange>>)
+    -> Option<RawRefer<ForwardEnt<AdrRange>>>
+{
+    match prev {
+        None => list.pop_front(),
+        Some(p) => list.remove_next(p),
+    }
+}
+
+/// `SLOTS` must not exceed the bits of SlotMask.
+/// `RANGES` is the number of built-in AdrRange entries; more entries can be
+/// added by `add_range_buf()` or `grow_range_buf()`.
+/// CheapAlloc must not be moved after `init_with_slotdefs()`.
+///
+/// static mut HEAP: CheapAlloc = CheapAlloc::new();
+pub struct CheapAlloc<const SLOTS: usize = SLOT_NUM,
+                      const RANGES: usize = RANGE_NUM> {
     free_buf_list:
         SingleForwardList::<AdrRange, RawRefer<ForwardEnt<AdrRange>>>,
 
-    slots: [AdrSlot; SLOT_NUM],
-    range_buf: [ForwardEnt<AdrRange>; 256],
+    slots: [AdrSlot; SLOTS],
+    range_buf: [ForwardEnt<AdrRange>; RANGES],
+}
+
+// SAFETY: free_buf_list is an empty list, slots are zeroed AdrSlot and
+// range_buf holds unused AdrRange entries with null links.  The entries
+// are linked to free_buf_list by init_with_slotdefs().
+unsafe impl<const SLOTS: usize, const RANGES: usize> Zeroable
+    for CheapAlloc<SLOTS, RANGES> {}
+
+impl<const SLOTS: usize, const RANGES: usize> Default
+    for CheapAlloc<SLOTS, RANGES>
+{
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-pub const USIZES_IN_CHEAPALLOC: usize = 
-    (size_of::<CheapAlloc>() + size_of::<usize>() - 1) / size_of::<usize>();
+// The raw pointers refer to range_buf and the managed memory, which are
+// not bound to a thread.
+unsafe impl<const SLOTS: usize, const RANGES: usize> Send
+    for CheapAlloc<SLOTS, RANGES> {}
 
-impl CheapAlloc {
+impl<const SLOTS: usize, const RANGES: usize> CheapAlloc<SLOTS, RANGES> {
 
-    pub fn from<'buf>(buf: &'buf mut [usize; USIZES_IN_CHEAPALLOC])
-        -> &'buf mut Self
-    {
-        let rawalloc = unsafe { buf.as_mut_ptr() } as *mut Self;
-        unsafe { &mut *rawalloc }
+    /// Evaluated where used, so a bad `SLOTS` fails to compile.
+    const SLOTS_FIT: () = assert!(SLOTS <= SlotMask::BITS as usize);
+
+    /// Nothing is available until `init_with_slotdefs()`.
+    pub const fn new() -> Self {
+        let () = Self::SLOTS_FIT;
+        zeroable::zeroed()
     }
 
-    pub fn init_with_slotdefs(&mut self, defs: &SlotDefs) {
+    pub fn init_with_slotdefs(&mut self, defs: &SlotDefs<SLOTS>) {
+        let () = Self::SLOTS_FIT;
+
         // initialize.
         self.free_buf_list =
             SingleForwardList::<AdrRange, RawRefer<ForwardEnt<AdrRange>>>::
@@ -133,109 +206,494 @@ impl CheapAlloc {
         }
     }
 
-    pub fn add_free_range(&mut self, slot: usize, adr: usize, bytes: usize)
+    /// Add `[adr, adr + bytes)` to the free ranges of `slot`.  Adjacent
+    /// free ranges are merged.  It fails with Inval if the range is out of
+    /// the slot or overlaps a free or used range.  Forgotten ranges are not
+    /// tracked, so overlaps with them are not detected.
+    /// Page 0 is never handed out since X can't hold a null pointer, so a
+    /// range in page 0 fails with Inval too.
+    pub fn add_free_range(&mut self, slot: usize, adr: VirtAddr, bytes: usize)
         -> Result<(), Error>
     {
+        if slot >= SLOTS {
+            return Err(Error::Inval);
+        }
         if bytes == 0 {
             return Ok(());
         }
-        let r = self.new_adrrange();
-        match r {
-            Err(e) => Err(e),
-            Ok(ent) => {
-                ent.ref_elem_mut().set(adr, bytes);
-                self.slots[slot].free_ranges.push_front(ent.into());
-                Ok(())
-            }
+        let adr = adr.get();
+        // ranges are handled by the exclusive end.
+        if adr.checked_add(bytes).is_none() {
+            return Err(Error::Inval.context("end of the address space"));
+        }
+        let last = adr + (bytes - 1);
+        let s = &self.slots[slot];
+        if adr < s.start || s.end < last {
+            return Err(Error::Inval.context("out of the slot"));
+        }
+        if adr < ops::PAGE_SIZE {
+            return Err(Error::Inval.context("page 0"));
+        }
+        let overlapped = iter_ranges(&s.free_ranges)
+            .chain(iter_ranges(&s.used_ranges))
+            .any(|r| adr <= r.adr + (r.bytes - 1) && r.adr <= last);
+        if overlapped {
+            return Err(Error::Inval.context("overlapped range"));
+        }
+
+        let ent = self.new_adrrange()?;
+        ent.set(adr, bytes);
+        self._free(slot, ent.into());
+        Ok(())
+    }
+
+    /// Add `[adr, adr + bytes)` as the memory for AdrRange entries.
+    /// Returns the number of entries added.
+    ///
+    /// # Safety
+    ///
+    /// The memory must be unused and must live while self is used.
+    pub unsafe fn add_range_buf(&mut self, adr: VirtAddr, bytes: usize)
+        -> usize
+    {
+        let ent_size = size_of::<ForwardEnt<AdrRange>>();
+        let start = match adr.up_align(align_of::<ForwardEnt<AdrRange>>()) {
+            Some(start) => start,
+            None => return 0,
+        };
+        let gap = start.get() - adr.get();
+        if bytes < gap + ent_size {
+            return 0;
+        }
+        let num = (bytes - gap) / ent_size;
+
+        let ents = start.as_mut_ptr::<ForwardEnt<AdrRange>>();
+        for i in 0..num {
+            let ent = ents.add(i);
+            ptr::write(ent, ForwardEnt::new(AdrRange::new()));
+            self.free_buf_list.push_front((&mut *ent).into());
         }
+        num
     }
 
-    fn _new() -> Self {
-        Self {
-            free_buf_list:
-                SingleForwardList::<AdrRange, RawRefer<ForwardEnt<AdrRange>>>::
-                new(),
-            slots:
-                unsafe { generate_with0!([AdrSlot; SLOT_NUM]) },
-            /* or safe represents:
-             * [
-             *   AdrSlot::new(), AdrSlot::new(), AdrSlot::new(), AdrSlot::new(),
-             *   AdrSlot::new(), AdrSlot::new(), AdrSlot::new(), AdrSlot::new()
-             * ],
-             */
-            range_buf:
-                unsafe { generate_with0!([ForwardEnt<AdrRange>; 256]) },
-        }
-    }
-
-    fn _alloc<Type>(
+    /// Allocate `bytes` from `slotmask` permanently and add it as the
+    /// memory for AdrRange entries.
+    pub fn grow_range_buf(&mut self, slotmask: SlotMask, bytes: usize)
+        -> Result<usize, Error>
+    {
+        let layout =
+            Layout::from_size_align(bytes, align_of::<ForwardEnt<AdrRange>>())
+            .map_err(|_| Error::Inval)?;
+        // the memory is forgotten, so it must hold at least one entry.
+        if bytes < size_of::<ForwardEnt<AdrRange>>() {
+            return Err(Error::Inval);
+        }
+        let (_, adr) = self._alloc_masked(slotmask, layout, true)?;
+        Ok(unsafe { self.add_range_buf(VirtAddr::new(adr), bytes) })
+    }
+
+    /// First-fit search in `slot`.  Returns the carved address, or None if
+    /// no free range fits.
+    fn _alloc(
         &mut self,
-        slot: &mut AdrSlot,
+        slot: usize,
         layout: Layout,
-        forget: bool) -> Option<usize>
+        forget: bool) -> Result<Option<usize>, Error>
     {
-        let mut align_gap;
-        let mut found: Option<&mut AdrRange> = None;
+        let mut prev: Option<&mut ForwardEnt<AdrRange>> = None;
+        let mut cur = self.slots[slot].free_ranges.get_front();
+        let mut adr = 0;
 
-        for e in slot.free_ranges.iter() {
-            if e.bytes < layout.size() {
-                continue;
-            }
-            align_gap = ops::up_align(e.adr, layout.align()) - e.adr;
-            if e.bytes - align_gap >= layout.size() {
-                found = Some(e);
-                break;
+        while let Some(e) = cur {
+            if let Some(a) = ops::up_align(e.adr, layout.align()) {
+                adr = a;
+                let align_gap = adr - e.adr;
+                if align_gap <= e.bytes && e.bytes - align_gap >= layout.size()
+                {
+                    cur = Some(e);
+                    break;
+                }
             }
+            cur = self.slots[slot].free_ranges.get_next(e);
+            prev = Some(e);
         }
-        let ent = match found {
-            None => return None,
-            Some(x) => x,
+        let ent = match cur {
+            Some(ent) => ent,
+            None => return Ok(None),
         };
 
-        if ent.bytes == layout.size() {
-            //adr = ent.adr;
-            //slot.free_ranges.remove
+        self._carve(slot, prev, ent, adr, layout.size(), forget)?;
+        Ok(Some(adr))
+    }
+
+    /// Cut `[adr, adr + bytes)` out of the free range `ent` which follows
+    /// `prev` in `slot`, and move it to used ranges.
+    fn _carve(
+        &mut self,
+        slot: usize,
+        prev: Option<&mut ForwardEnt<AdrRange>>,
+        ent: &mut ForwardEnt<AdrRange>,
+        adr: usize,
+        bytes: usize,
+        forget: bool) -> Result<(), Error>
+    {
+        let head_bytes = adr - ent.adr;
+        let tail_bytes = ent.bytes - head_bytes - bytes;
+
+        if head_bytes == 0 && tail_bytes == 0 {
+            // The whole range is used, move it to used_ranges.
+            let r = unlink_range(&mut self.slots[slot].free_ranges, prev)
+                .ok_or(Error::Inval)?;
+            if forget {
+                self.slots[slot].forgotten_bytes += bytes;
+                self.delete_adrrange(r);
+            } else {
+                self.slots[slot].used_ranges.push_front(r);
+            }
+            return Ok(());
         }
 
-        //let mut adr;
-        //if ent.bytes == layout.size() {
-        //    adr = ent.adr;
-        //    slot.free_ranges.
-        //} else {
-        //}
+        let used = if forget {
+            None
+        } else {
+            Some(self.new_adrrange()?)
+        };
+        if head_bytes != 0 && tail_bytes != 0 {
+            // Split into head, used and tail.
+            let tail = match self.new_adrrange() {
+                Ok(x) => x,
+                Err(e) => {
+                    if let Some(used) = used {
+                        self.delete_adrrange(used.into());
+                    }
+                    return Err(e);
+                },
+            };
+            tail.set(adr + bytes, tail_bytes);
+            self.slots[slot].free_ranges.insert_after(ent, tail.into());
+            ent.bytes = head_bytes;
+        } else if head_bytes != 0 {
+            ent.bytes = head_bytes;
+        } else {
+            ent.set(adr + bytes, tail_bytes);
+        }
 
-        None
+        match used {
+            Some(used) => {
+                used.set(adr, bytes);
+                self.slots[slot].used_ranges.push_front(used.into());
+            },
+            None => {
+                self.slots[slot].forgotten_bytes += bytes;
+            },
+        }
+
+        Ok(())
     }
 
-    pub fn alloc<Type>(
+    /// Returns (slot, address).
+    fn _alloc_masked(
         &mut self,
         slotmask: SlotMask,
         layout: Layout,
-        forget: bool) -> Result<X<Type>, Error> {
+        forget: bool) -> Result<(usize, usize), Error>
+    {
+        if layout.size() == 0 {
+            return Err(Error::Inval);
+        }
 
-        for (i, slot) in self.slots.iter_mut().enumerate() {
-            //write!(log(), "i: {}, start: {}, end: {}", i, slot.start, slot.end);
+        for i in 0..SLOTS {
             if !is_masked(i, slotmask) {
                 continue;
             }
+            if let Some(adr) = self._alloc(i, layout, forget)? {
+                return Ok((i, adr));
+            }
+        }
+
+        Err(Error::NoMemFor { bytes: layout.size() })
+    }
+
+    fn _owner(&mut self, forget: bool) -> Option<*mut dyn Dealloc> {
+        if forget {
+            None
+        } else {
+            Some(self as *mut Self as *mut dyn Dealloc)
+        }
+    }
+
+    /// Allocate from the first slot in `slotmask` which has enough space.
+    /// If `forget` is true, the memory is handed off permanently: it is
+    /// not tracked in used ranges, cannot be freed and is counted by
+    /// `forgotten_bytes()` instead.
+    /// The returned X frees the memory on drop unless `forget` is true.
+    ///
+    /// # Safety
+    ///
+    /// Unless `forget` is true, the returned X refers to self to free the
+    /// memory on drop.  It must be dropped while self is alive, is not
+    /// moved and is not borrowed.  If self is behind a lock, drop it with
+    /// the guard held, or take the pointer with `X::into_raw()`.
+    pub unsafe fn alloc<Type>(
+        &mut self,
+        slotmask: SlotMask,
+        layout: Layout,
+        forget: bool) -> Result<X<MaybeUninit<Type>>, Error> {
+
+        let (slot, adr) = self._alloc_masked(slotmask, layout, forget)?;
+        let owner = self._owner(forget);
+        Ok(X::from_raw_in(adr as *mut MaybeUninit<Type>, owner, slot))
+    }
+
+    /// Allocate an array of `len` elements.
+    ///
+    /// # Safety
+    ///
+    /// The same as `alloc()`.
+    pub unsafe fn alloc_slice<Type>(
+        &mut self,
+        slotmask: SlotMask,
+        len: usize,
+        forget: bool) -> Result<X<[MaybeUninit<Type>]>, Error> {
+
+        let layout = Layout::array::<Type>(len).map_err(|_| Error::Inval)?;
+        let (slot, adr) = self._alloc_masked(slotmask, layout, forget)?;
+        let owner = self._owner(forget);
+        let ptr = ptr::slice_from_raw_parts_mut(
+            adr as *mut MaybeUninit<Type>, len);
+        Ok(X::from_raw_in(ptr, owner, slot))
+    }
+
+    /// Mark the fixed range `[adr, adr + bytes)` in `slot` as used.
+    /// Adjacent free ranges are always merged, so it fails only if any part
+    /// of the range is already used or not free.
+    pub fn reserve(
+        &mut self,
+        slot: usize,
+        adr: VirtAddr,
+        bytes: usize,
+        forget: bool) -> Result<(), Error>
+    {
+        if slot >= SLOTS {
+            return Err(Error::Inval);
+        }
+        if bytes == 0 {
+            return Ok(());
+        }
+        let adr = adr.get();
+        let end = adr.checked_add(bytes).ok_or(Error::Range)?;
+
+        let mut prev: Option<&mut ForwardEnt<AdrRange>> = None;
+        let mut cur = self.slots[slot].free_ranges.get_front();
+        while let Some(e) = cur {
+            if e.adr <= adr && end <= e.adr + e.bytes {
+                return self._carve(slot, prev, e, adr, bytes, forget);
+            }
+            cur = self.slots[slot].free_ranges.get_next(e);
+            prev = Some(e);
+        }
+
+        // a part of the range is used or not managed.
+        Err(Error::Busy)
+    }
+
+    /// Bytes handed off permanently from `slot`.
+    pub fn forgotten_bytes(&self, slot: usize) -> usize {
+        self.slots[slot].forgotten_bytes
+    }
 
-            for range in slot.free_ranges.iter() {
-                //if bytes < range.bytes
+    pub fn slot_stat(&self, slot: usize) -> SlotStat {
+        let s = &self.slots[slot];
+        let mut stat = SlotStat {
+            forgotten_bytes: s.forgotten_bytes,
+            ..SlotStat::default()
+        };
+        for r in iter_ranges(&s.free_ranges) {
+            stat.free_bytes += r.bytes;
+            stat.free_fragments += 1;
+            if stat.largest_free < r.bytes {
+                stat.largest_free = r.bytes;
             }
+        }
+        for r in iter_ranges(&s.used_ranges) {
+            stat.used_bytes += r.bytes;
+            stat.used_fragments += 1;
+        }
+        stat.total_bytes =
+            stat.free_bytes + stat.used_bytes + stat.forgotten_bytes;
+        stat
+    }
+
+    /// (address, bytes) of free ranges in `slot`.  Not sorted.
+    pub fn free_ranges(&self, slot: usize)
+        -> impl Iterator<Item = (VirtAddr, usize)> + '_
+    {
+        iter_ranges(&self.slots[slot].free_ranges)
+            .map(|r| (VirtAddr::new(r.adr), r.bytes))
+    }
 
+    /// (address, bytes) of allocated or reserved ranges in `slot`.  Not
+    /// sorted.  Forgotten ranges are not included.
+    pub fn used_ranges(&self, slot: usize)
+        -> impl Iterator<Item = (VirtAddr, usize)> + '_
+    {
+        iter_ranges(&self.slots[slot].used_ranges)
+            .map(|r| (VirtAddr::new(r.adr), r.bytes))
+    }
+
+    /// Number of AdrRange entries not in use.
+    pub fn spare_entries(&self) -> usize {
+        let mut cur = self.free_buf_list.get_front();
+        let mut n = 0;
+        while let Some(e) = cur {
+            n += 1;
+            cur = self.free_buf_list.get_next(e);
+        }
+        n
+    }
+
+    /// Write statistics and all ranges of non-empty slots.
+    pub fn dump<W: fmt::Write + ?Sized>(&self, w: &mut W) -> fmt::Result {
+        for (i, s) in self.slots.iter().enumerate() {
+            let stat = self.slot_stat(i);
+            if stat.total_bytes == 0 {
+                continue;
+            }
+            writeln!(w, "slot {}: {:#x}-{:#x} total={:#x} free={:#x} \
+                         used={:#x} forgotten={:#x} largest={:#x} frags={}",
+                i, s.start, s.end, stat.total_bytes, stat.free_bytes,
+                stat.used_bytes, stat.forgotten_bytes, stat.largest_free,
+                stat.free_fragments)?;
+            for r in iter_ranges(&s.free_ranges) {
+                writeln!(w, "  free {:#x}-{:#x}", r.adr, r.adr + r.bytes - 1)?;
+            }
+            for r in iter_ranges(&s.used_ranges) {
+                writeln!(w, "  used {:#x}-{:#x}", r.adr, r.adr + r.bytes - 1)?;
+            }
         }
+        writeln!(w, "spare entries: {}", self.spare_entries())
+    }
 
-        Err(Error::Fail)
+    /// Detach the used range starting at `adr` in `slot`.
+    fn _take_used_in(&mut self, slot: usize, adr: usize)
+        -> Option<RawRefer<ForwardEnt<AdrRange>>>
+    {
+        let mut cursor = self.slots[slot].used_ranges.cursor_front_mut();
+        while let Some(r) = cursor.current() {
+            if r.adr == adr {
+                return cursor.remove_current();
+            }
+            cursor.move_next();
+        }
+        None
+    }
 
+    /// Detach the used range starting at `adr`.
+    fn _take_used(&mut self, adr: usize)
+        -> Option<(usize, RawRefer<ForwardEnt<AdrRange>>)>
+    {
+        for i in 0..SLOTS {
+            if let Some(r) = self._take_used_in(i, adr) {
+                return Some((i, r));
+            }
+        }
+        None
+    }
+
+    /// Return `ent` to the free ranges of `slot`, merging with adjacent
+    /// free ranges.
+    fn _free(&mut self, slot: usize, ent: RawRefer<ForwardEnt<AdrRange>>) {
+        let adr = ent.adr;
+        let end = ent.adr + ent.bytes;
+
+        let mut prev: Option<&mut ForwardEnt<AdrRange>> = None;
+        let mut upper: Option<&mut ForwardEnt<AdrRange>> = None;
+        let mut cur = self.slots[slot].free_ranges.get_front();
+        while let Some(e) = cur {
+            if e.adr == end {
+                upper = Some(e);
+                break;
+            }
+            cur = self.slots[slot].free_ranges.get_next(e);
+            prev = Some(e);
+        }
+
+        let lower = self.slots[slot].free_ranges.iter()
+            .find(|r| r.adr + r.bytes == adr);
+
+        match (lower, upper) {
+            (Some(l), Some(u)) => {
+                l.bytes += ent.bytes + u.bytes;
+                let u = unlink_range(&mut self.slots[slot].free_ranges, prev);
+                self.delete_adrrange(ent);
+                if let Some(u) = u {
+                    self.delete_adrrange(u);
+                }
+            },
+            (Some(l), None) => {
+                l.bytes += ent.bytes;
+                self.delete_adrrange(ent);
+            },
+            (None, Some(u)) => {
+                let bytes = ent.bytes + u.bytes;
+                u.set(adr, bytes);
+                self.delete_adrrange(ent);
+            },
+            (None, None) => {
+                self.slots[slot].free_ranges.push_front(ent);
+            },
+        }
+    }
+
+    /// Free the memory allocated at `adr`.
+    pub fn free(&mut self, adr: VirtAddr) -> Result<(), Error> {
+        match self._take_used(adr.get()) {
+            Some((slot, ent)) => {
+                self._free(slot, ent);
+                Ok(())
+            },
+            None => Err(Error::BadAdrAt { adr: adr.get() }),
+        }
+    }
+
+    /// Drop the value of `x` and free the memory.
+    pub fn dealloc<Type: ?Sized>(&mut self, x: X<Type>) -> Result<(), Error> {
+        let ptr = X::into_raw(x);
+        unsafe { ptr::drop_in_place(ptr) };
+        self.free(VirtAddr::from_ptr(ptr))
     }
 
     fn new_adrrange<'s, 't>(&'s mut self)
         -> Result<&'t mut ForwardEnt<AdrRange>, Error>
     {
-        let mut r = self.free_buf_list.pop_front();
+        let r = self.free_buf_list.pop_front();
         match r {
             Some(mut buf) => Ok(buf.as_mut()),
-            None => Err(Error::Fail),
+            None => Err(Error::NoMem.context("AdrRange entries")),
+        }
+    }
+
+    fn delete_adrrange(&mut self, ent: RawRefer<ForwardEnt<AdrRange>>) {
+        self.free_buf_list.push_front(ent);
+    }
+}
+
+impl<const SLOTS: usize, const RANGES: usize> fmt::Debug
+    for CheapAlloc<SLOTS, RANGES>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.dump(f)
+    }
+}
+
+impl<const SLOTS: usize, const RANGES: usize> Dealloc
+    for CheapAlloc<SLOTS, RANGES>
+{
+    fn dealloc_x(&mut self, slot: usize, adr: usize) {
+        if slot == ANY_SLOT {
+            let _ = self.free(VirtAddr::new(adr));
+        } else if let Some(ent) = self._take_used_in(slot, adr) {
+            self._free(slot, ent);
         }
     }
 }
@@ -243,17 +701,378 @@ impl CheapAlloc {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::error::Code;
+
+    /// `alloc()` for tests, which drop X while the allocator is alive and
+    /// not borrowed.
+    trait TestAlloc {
+        fn talloc<Type>(&mut self, slotmask: SlotMask, layout: Layout,
+                        forget: bool)
+            -> Result<X<MaybeUninit<Type>>, Error>;
+    }
+
+    impl<const S: usize, const R: usize> TestAlloc for CheapAlloc<S, R> {
+        fn talloc<Type>(&mut self, slotmask: SlotMask, layout: Layout,
+                        forget: bool)
+            -> Result<X<MaybeUninit<Type>>, Error>
+        {
+            unsafe { self.alloc(slotmask, layout, forget) }
+        }
+    }
 
     #[test]
     fn test() {
         assert_eq!(2 + 2, 4);
         let mut defs = SlotDefs::new();
-        defs.set(0, 0x00000, 0x0ffff);
-        defs.set(1, 0x10000, 0x1ffff);
+        defs.set(0, VirtAddr::new(0x00000), VirtAddr::new(0x0ffff));
+        defs.set(1, VirtAddr::new(0x10000), VirtAddr::new(0x1ffff));
+
+        let mut ca: CheapAlloc = CheapAlloc::new();
+        ca.init_with_slotdefs(&defs);
+        assert!(ca.talloc::<[u8; 0x10000]>(0x1 | 0x2, unsafe { Layout::from_size_align_unchecked(0x10000, 8) }, false).is_err());
+    }
+
+    fn layout(size: usize, align: usize) -> Layout {
+        Layout::from_size_align(size, align).unwrap()
+    }
+
+    fn adr<T: ?Sized>(x: X<T>) -> usize {
+        X::into_raw(x) as *mut u8 as usize
+    }
+
+    #[test]
+    fn test_alloc() {
+        let mut defs = SlotDefs::new();
+        defs.set(0, VirtAddr::new(0x00000), VirtAddr::new(0x0ffff));
+        defs.set(1, VirtAddr::new(0x10000), VirtAddr::new(0x1ffff));
+
+        let mut ca: CheapAlloc = CheapAlloc::new();
+        ca.init_with_slotdefs(&defs);
+        assert!(ca.add_free_range(0, VirtAddr::new(0x1004), 0x100).is_ok());
+        assert!(ca.add_free_range(1, VirtAddr::new(0x10000), 0x1000).is_ok());
+
+        // aligned, leaves head and tail.
+        let x = ca.talloc::<u64>(0x1, layout(0x10, 0x10), false).unwrap();
+        assert_eq!(adr(x), 0x1010);
+        // fills the head.
+        let x = ca.talloc::<u64>(0x1, layout(0xc, 4), false).unwrap();
+        assert_eq!(adr(x), 0x1004);
+        // cuts from the front of tail.
+        let x = ca.talloc::<u64>(0x1, layout(0x20, 8), false).unwrap();
+        assert_eq!(adr(x), 0x1020);
+        // too large for slot 0, falls through to slot 1.
+        let x = ca.talloc::<u64>(0x1 | 0x2, layout(0x800, 8), false).unwrap();
+        assert_eq!(adr(x), 0x10000);
+        // takes the rest of slot 0 exactly.
+        let x = ca.talloc::<u64>(0x1, layout(0xc4, 4), false).unwrap();
+        assert_eq!(adr(x), 0x1040);
+        assert!(ca.talloc::<u64>(0x1, layout(1, 1), false).is_err());
+        // masked out.
+        assert!(ca.talloc::<u64>(0x4, layout(1, 1), false).is_err());
+        assert_eq!(ca.talloc::<u64>(0x2, layout(0, 1), false).err(),
+                   Some(Error::Inval));
+    }
+
+    #[test]
+    fn test_free() {
+        let mut defs = SlotDefs::new();
+        defs.set(0, VirtAddr::new(0x00000), VirtAddr::new(0x0ffff));
+
+        let mut ca: CheapAlloc = CheapAlloc::new();
+        ca.init_with_slotdefs(&defs);
+        assert!(ca.add_free_range(0, VirtAddr::new(0x1000), 0x400).is_ok());
+
+        let x1 = ca.talloc::<u8>(0x1, layout(0x100, 8), false).unwrap();
+        let x2 = ca.talloc::<u8>(0x1, layout(0x100, 8), false).unwrap();
+        let x3 = ca.talloc::<u8>(0x1, layout(0x100, 8), false).unwrap();
+        let x4 = ca.talloc::<u8>(0x1, layout(0x100, 8), false).unwrap();
+        let (a1, a3) = (adr(x1), adr(x3));
+        assert_eq!(adr(x4), 0x1300);
+        assert!(ca.talloc::<u8>(0x1, layout(1, 1), false).is_err());
+
+        // no neighbours.
+        assert!(ca.dealloc(x2).is_ok());
+        // merges with the upper neighbour.
+        assert!(ca.free(VirtAddr::new(a1)).is_ok());
+        assert_eq!(ca.free(VirtAddr::new(a1)),
+                   Err(Error::BadAdrAt { adr: a1 }));
+        // merges with the lower neighbour.
+        assert!(ca.free(VirtAddr::new(0x1300)).is_ok());
+        assert_eq!(ca.talloc::<u8>(0x1, layout(0x201, 1), false).err(),
+                   Some(Error::NoMemFor { bytes: 0x201 }));
+        // merges with both.
+        assert!(ca.free(VirtAddr::new(a3)).is_ok());
+        assert_eq!(ca.slots[0].free_ranges.iter().count(), 1);
+
+        let x = ca.talloc::<u8>(0x1, layout(0x400, 8), false).unwrap();
+        assert_eq!(adr(x), 0x1000);
+        assert!(ca.free(VirtAddr::new(0x1000)).is_ok());
+
+        // bookkeeping entries are recycled.
+        for _ in 0..1000 {
+            let x1 = ca.talloc::<u8>(0x1, layout(0x10, 0x10), false).unwrap();
+            let x2 = ca.talloc::<u8>(0x1, layout(0x10, 0x10), false).unwrap();
+            assert!(ca.dealloc(x1).is_ok());
+            assert!(ca.dealloc(x2).is_ok());
+        }
+        assert_eq!(ca.free_buf_list.iter().count(), 255);
+    }
+
+    #[test]
+    fn test_forget() {
+        let mut defs = SlotDefs::new();
+        defs.set(0, VirtAddr::new(0x00000), VirtAddr::new(0x0ffff));
+
+        let mut ca: CheapAlloc = CheapAlloc::new();
+        ca.init_with_slotdefs(&defs);
+        assert!(ca.add_free_range(0, VirtAddr::new(0x1000), 0x300).is_ok());
+        assert_eq!(ca.free_buf_list.iter().count(), 255);
+
+        let x1 = ca.talloc::<u8>(0x1, layout(0x100, 8), true).unwrap();
+        let x2 = ca.talloc::<u8>(0x1, layout(0x100, 8), false).unwrap();
+        let x3 = ca.talloc::<u8>(0x1, layout(0x100, 8), true).unwrap();
+        assert_eq!(adr(x3), 0x1200);
+        // only x2 consumes an entry.
+        assert_eq!(ca.free_buf_list.iter().count(), 255);
+        assert_eq!(ca.slots[0].used_ranges.iter().count(), 1);
+        assert_eq!(ca.forgotten_bytes(0), 0x200);
 
-        let mut buf = [0usize; USIZES_IN_CHEAPALLOC];
-        let mut ca = CheapAlloc::from(&mut buf);
+        assert!(ca.dealloc(x1).is_err());
+        assert!(ca.dealloc(x2).is_ok());
+        assert_eq!(ca.free_buf_list.iter().count(), 255);
+        assert_eq!(ca.forgotten_bytes(0), 0x200);
+    }
+
+    #[test]
+    fn test_reserve() {
+        let mut defs = SlotDefs::new();
+        defs.set(0, VirtAddr::new(0x00000), VirtAddr::new(0x0ffff));
+
+        let mut ca: CheapAlloc = CheapAlloc::new();
+        ca.init_with_slotdefs(&defs);
+        assert!(ca.add_free_range(0, VirtAddr::new(0x1000), 0x1000).is_ok());
+
+        assert!(ca.reserve(0, VirtAddr::new(0x1400), 0x100, false).is_ok());
+        // partially used.
+        assert_eq!(ca.reserve(0, VirtAddr::new(0x1380), 0x100, false),
+                   Err(Error::Busy));
+        assert!(ca.reserve(0, VirtAddr::new(0x14ff), 0x10, false).is_err());
+        // not in free ranges.
+        assert!(ca.reserve(0, VirtAddr::new(0x1f00), 0x200, false).is_err());
+        assert!(ca.reserve(0, VirtAddr::new(0xb8000), 0x8000, false).is_err());
+        // whole free range at the front.
+        assert!(ca.reserve(0, VirtAddr::new(0x1000), 0x400, true).is_ok());
+        assert_eq!(ca.forgotten_bytes(0), 0x400);
+
+        let x = ca.talloc::<u8>(0x1, layout(0x10, 1), false).unwrap();
+        assert_eq!(adr(x), 0x1500);
+
+        assert!(ca.free(VirtAddr::new(0x1400)).is_ok());
+        assert!(ca.reserve(0, VirtAddr::new(0x1400), 0x100, false).is_ok());
+        assert!(ca.reserve(0, VirtAddr::new(0x1000), 0x10, false).is_err());
+    }
+
+    #[test]
+    fn test_add_free_range() {
+        let mut defs = SlotDefs::new();
+        defs.set(0, VirtAddr::new(0x00000), VirtAddr::new(0x0ffff));
+
+        let mut ca: CheapAlloc = CheapAlloc::new();
+        ca.init_with_slotdefs(&defs);
+        // a free region given in pieces is merged.
+        assert!(ca.add_free_range(0, VirtAddr::new(0x2000), 0x1000).is_ok());
+        assert!(ca.add_free_range(0, VirtAddr::new(0x1000), 0x1000).is_ok());
+        assert!(ca.add_free_range(0, VirtAddr::new(0x3000), 0x1000).is_ok());
+        assert_eq!(ca.slot_stat(0).free_fragments, 1);
+        assert_eq!(ca.spare_entries(), 255);
+        assert!(ca.reserve(0, VirtAddr::new(0x1f00), 0x1200, false).is_ok());
+
+        let code = |r: Result<(), Error>| r.map_err(|e| e.code());
+        // overlaps free and used ranges.
+        assert_eq!(code(ca.add_free_range(0, VirtAddr::new(0x3800), 0x1000)),
+                   Err(Code::Inval));
+        assert_eq!(code(ca.add_free_range(0, VirtAddr::new(0x2000), 0x10)),
+                   Err(Code::Inval));
+        // out of the slot.
+        assert_eq!(code(ca.add_free_range(0, VirtAddr::new(0xff00), 0x200)),
+                   Err(Code::Inval));
+        assert_eq!(code(ca.add_free_range(8, VirtAddr::new(0x8000), 0x10)),
+                   Err(Code::Inval));
+        // page 0 is never handed out.
+        assert_eq!(code(ca.add_free_range(0, VirtAddr::new(0x0), 0x800)),
+                   Err(Code::Inval));
+        assert_eq!(ca.slot_stat(0).total_bytes, 0x3000);
+    }
+
+    #[test]
+    fn test_add_free_range_top() {
+        let top = usize::MAX - 0xffff;
+        let mut defs = SlotDefs::new();
+        defs.set(0, VirtAddr::new(top), VirtAddr::new(usize::MAX));
+
+        let mut ca: CheapAlloc = CheapAlloc::new();
+        ca.init_with_slotdefs(&defs);
+        // the end is not representable.
+        let r = ca.add_free_range(0, VirtAddr::new(usize::MAX - 0xfff), 0x1000);
+        assert_eq!(r.map_err(|e| e.code()), Err(Code::Inval));
+        assert!(ca.add_free_range(0, VirtAddr::new(top), 0x1000).is_ok());
+        let r = ca.add_free_range(0, VirtAddr::new(top + 0x1000), 0xefff);
+        assert!(r.is_ok());
+        assert_eq!(ca.slot_stat(0).free_fragments, 1);
+        assert!(ca.reserve(0, VirtAddr::new(top + 0xf000), 0xfff, false)
+            .is_ok());
+        assert_eq!(ca.slot_stat(0).free_bytes, 0xf000);
+        assert!(ca.free(VirtAddr::new(top + 0xf000)).is_ok());
+        assert_eq!(ca.slot_stat(0).largest_free, 0xffff);
+    }
+
+    #[test]
+    fn test_x() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+        struct Data(usize);
+        impl Drop for Data {
+            fn drop(&mut self) {
+                DROPS.fetch_add(self.0, Ordering::Relaxed);
+            }
+        }
+
+        let mut defs = SlotDefs::new();
+        defs.set(0, VirtAddr::new(0), VirtAddr::new(usize::MAX));
+
+        let mut mem = [0u64; 0x100];
+        let mut ca: CheapAlloc = CheapAlloc::new();
+        ca.init_with_slotdefs(&defs);
+        let start = VirtAddr::from_ptr(mem.as_mut_ptr());
+        assert!(ca.add_free_range(0, start, 0x800).is_ok());
+
+        {
+            let x = ca.talloc::<Data>(0x1, Layout::new::<Data>(), false).unwrap();
+            let mut x = X::write(x, Data(1));
+            x.0 += 1;
+            assert_eq!(ca.slots[0].used_ranges.iter().count(), 1);
+        }
+        assert_eq!(DROPS.load(Ordering::Relaxed), 2);
+        assert_eq!(ca.slots[0].used_ranges.iter().count(), 0);
+
+        {
+            let x = unsafe { ca.alloc_slice::<u64>(0x1, 0x100, false) };
+            let mut x = x.unwrap();
+            assert_eq!(x.len(), 0x100);
+            for (i, e) in x.iter_mut().enumerate() {
+                *e = MaybeUninit::new(i as u64);
+            }
+            assert!(ca.talloc::<u8>(0x1, layout(1, 1), false).is_err());
+        }
+        assert_eq!(mem[0xff], 0xff);
+        assert_eq!(ca.slots[0].used_ranges.iter().count(), 0);
+
+        let x = ca.talloc::<Data>(0x1, Layout::new::<Data>(), false).unwrap();
+        let x = X::leak(X::write(x, Data(4)));
+        assert_eq!(x.0, 4);
+        assert_eq!(ca.slots[0].used_ranges.iter().count(), 1);
+        assert_eq!(DROPS.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_range_buf() {
+        let mut defs = SlotDefs::<2>::new();
+        defs.set(0, VirtAddr::new(0x00000), VirtAddr::new(0xfffff));
+        defs.set(1, VirtAddr::new(0x100000), VirtAddr::new(usize::MAX));
+
+        let mut mem = [0u64; 0x100];
+        let mut ca = CheapAlloc::<2, 2>::new();
+        ca.init_with_slotdefs(&defs);
+        assert!(ca.add_free_range(0, VirtAddr::new(0x1000), 0x100).is_ok());
+        assert!(ca.add_free_range(0, VirtAddr::new(0x2000), 0x100).is_ok());
+        assert_eq!(ca.add_free_range(0, VirtAddr::new(0x3000), 0x100)
+                   .map_err(|e| e.code()), Err(Code::NoMem));
+        // splitting a free range needs entries.
+        assert_eq!(ca.talloc::<u8>(0x1, layout(0x10, 1), false).err(),
+                   Some(Error::NoMem.context("AdrRange entries")));
+
+        // the unaligned head is skipped.
+        let ent_size = size_of::<ForwardEnt<AdrRange>>();
+        let adr = mem.as_mut_ptr() as usize;
+        let n1 = unsafe { ca.add_range_buf(VirtAddr::new(adr + 1), 0x100) };
+        assert_eq!(n1, (0x100 - 7) / ent_size);
+        assert!(ca.add_free_range(0, VirtAddr::new(0x3000), 0x100).is_ok());
+        assert!(
+            ca.add_free_range(1, VirtAddr::new(adr + 0x100), 0x700).is_ok());
+        let n2 = ca.grow_range_buf(0x2, 0x200).unwrap();
+        assert_eq!(n2, 0x200 / ent_size);
+        assert_eq!(ca.forgotten_bytes(1), 0x200);
+        assert_eq!(ca.free_buf_list.iter().count(), n1 + n2 - 2);
+        assert!(ca.grow_range_buf(0x2, 0x1000).is_err());
+        // too small for an entry, so nothing is allocated.
+        assert_eq!(ca.grow_range_buf(0x2, ent_size - 1).err(),
+                   Some(Error::Inval));
+        assert_eq!(ca.forgotten_bytes(1), 0x200);
+
+        assert!(ca.talloc::<u8>(0x1, layout(0x10, 1), false).is_ok());
+    }
+
+    #[test]
+    fn test_stat() {
+        extern crate std;
+        use std::string::String;
+        use std::vec::Vec;
+        use core::fmt::Write;
+
+        let mut defs = SlotDefs::new();
+        defs.set(0, VirtAddr::new(0x00000), VirtAddr::new(0x0ffff));
+        defs.set(1, VirtAddr::new(0x10000), VirtAddr::new(0x1ffff));
+
+        let mut ca: CheapAlloc = CheapAlloc::new();
+        ca.init_with_slotdefs(&defs);
+        assert!(ca.add_free_range(0, VirtAddr::new(0x1000), 0x1000).is_ok());
+        assert!(ca.add_free_range(0, VirtAddr::new(0x4000), 0x100).is_ok());
+        assert!(ca.reserve(0, VirtAddr::new(0x1000), 0x100, false).is_ok());
+        assert!(ca.reserve(0, VirtAddr::new(0x1800), 0x80, true).is_ok());
+
+        assert_eq!(ca.slot_stat(0), SlotStat {
+            total_bytes: 0x1100,
+            free_bytes: 0xf80,
+            used_bytes: 0x100,
+            forgotten_bytes: 0x80,
+            largest_free: 0x780,
+            free_fragments: 3,
+            used_fragments: 1,
+        });
+        assert_eq!(ca.slot_stat(1), SlotStat::default());
+        let mut free: Vec<_> = ca.free_ranges(0).map(|(a, b)| (a.get(), b))
+            .collect();
+        free.sort();
+        assert_eq!(free, [(0x1100, 0x700), (0x1880, 0x780), (0x4000, 0x100)]);
+        let used: Vec<_> = ca.used_ranges(0).map(|(a, b)| (a.get(), b))
+            .collect();
+        assert_eq!(used, [(0x1000, 0x100)]);
+        assert_eq!(ca.spare_entries(), 256 - 4);
+
+        let mut s = String::new();
+        write!(s, "{:?}", ca).unwrap();
+        assert_eq!(s,
+            "slot 0: 0x0-0xffff total=0x1100 free=0xf80 used=0x100 \
+             forgotten=0x80 largest=0x780 frags=3\n\
+             \x20 free 0x4000-0x40ff\n\
+             \x20 free 0x1100-0x17ff\n\
+             \x20 free 0x1880-0x1fff\n\
+             \x20 used 0x1000-0x10ff\n\
+             spare entries: 252\n");
+    }
+
+    static mut STATIC_CA: CheapAlloc<2, 4> = CheapAlloc::new();
+
+    #[test]
+    fn test_static() {
+        let ca = unsafe { &mut *core::ptr::addr_of_mut!(STATIC_CA) };
+        let mut defs = SlotDefs::new();
+        defs.set(0, VirtAddr::new(0x1000), VirtAddr::new(0x1fff));
         ca.init_with_slotdefs(&defs);
-        ca.alloc::<[u8; 0x10000]>(0x1 | 0x2, unsafe { Layout::from_size_align_unchecked(0x10000, 8) }, false);
+        assert_eq!(ca.spare_entries(), 4);
+        assert!(ca.add_free_range(0, VirtAddr::new(0x1000), 0x1000).is_ok());
+        assert_eq!(ca.talloc::<u8>(0x1, layout(0x10, 0x10), false).map(adr),
+                   Ok(0x1000));
     }
 }
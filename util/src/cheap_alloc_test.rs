@@ -0,0 +1,303 @@
+
+#![cfg(test)]
+
+//! Randomized tests of CheapAlloc against a simple reference model.
+
+extern crate std;
+
+use core::alloc::Layout;
+use std::vec::Vec;
+
+use super::boxed::X;
+use super::cheap_alloc::*;
+use super::error::{Code, Error};
+use super::ops::VirtAddr;
+
+const SLOTS: usize = 3;
+const SLOT_BYTES: usize = 0x10000;
+/// Page 0 can't be added, so slots start after it.
+const BASE: usize = 0x10000;
+
+/// xorshift64.
+struct Rand(u64);
+
+impl Rand {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// Error when AdrRange entries run out.
+fn no_entries() -> Error {
+    Error::NoMem.context("AdrRange entries")
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Block {
+    slot: usize,
+    adr: usize,
+    bytes: usize,
+}
+
+/// Reference model.  Free ranges are kept sorted and merged.
+struct Model {
+    free: [Vec<(usize, usize)>; SLOTS],  // (adr, end), end is exclusive.
+    used: Vec<Block>,
+    forgotten: [usize; SLOTS],
+    added: [usize; SLOTS],
+}
+
+impl Model {
+    fn new() -> Self {
+        Self {
+            free: [Vec::new(), Vec::new(), Vec::new()],
+            used: Vec::new(),
+            forgotten: [0; SLOTS],
+            added: [0; SLOTS],
+        }
+    }
+
+    fn add_free(&mut self, slot: usize, adr: usize, end: usize) {
+        let free = &mut self.free[slot];
+        free.push((adr, end));
+        free.sort();
+        let mut merged: Vec<(usize, usize)> = Vec::new();
+        for &(a, e) in free.iter() {
+            match merged.last_mut() {
+                Some(last) if last.1 == a => last.1 = e,
+                _ => merged.push((a, e)),
+            }
+        }
+        *free = merged;
+    }
+
+    fn remove_free(&mut self, slot: usize, adr: usize, end: usize) {
+        let free = &mut self.free[slot];
+        let i = free.iter().position(|&(a, e)| a <= adr && end <= e)
+            .expect("removed range is not free");
+        let (a, e) = free.remove(i);
+        if end < e {
+            free.insert(i, (end, e));
+        }
+        if a < adr {
+            free.insert(i, (a, adr));
+        }
+    }
+
+    fn covering_slot(&self, adr: usize, end: usize) -> Option<usize> {
+        (0..SLOTS).find(|&s| {
+            self.free[s].iter().any(|&(a, e)| a <= adr && end <= e)
+        })
+    }
+
+    fn fits(&self, slot: usize, layout: Layout) -> bool {
+        self.free[slot].iter().any(|&(a, e)| {
+            let adr = (a + layout.align() - 1) & !(layout.align() - 1);
+            adr + layout.size() <= e
+        })
+    }
+
+    fn overlaps_used(&self, adr: usize, end: usize) -> bool {
+        self.used.iter().any(|b| adr < b.adr + b.bytes && b.adr < end)
+    }
+}
+
+fn check(ca: &CheapAlloc<SLOTS, 32>, model: &Model) {
+    for slot in 0..SLOTS {
+        let stat = ca.slot_stat(slot);
+        let free = &model.free[slot];
+        let used: Vec<&Block> =
+            model.used.iter().filter(|b| b.slot == slot).collect();
+
+        assert_eq!(stat.free_bytes, free.iter().map(|r| r.1 - r.0).sum());
+        assert_eq!(stat.used_bytes, used.iter().map(|b| b.bytes).sum());
+        assert_eq!(stat.forgotten_bytes, model.forgotten[slot]);
+        // bytes are conserved.
+        assert_eq!(stat.total_bytes, model.added[slot]);
+        // adjacent free ranges are always merged.
+        assert_eq!(stat.free_fragments, free.len());
+        assert_eq!(stat.used_fragments, used.len());
+        assert_eq!(stat.largest_free,
+            free.iter().map(|r| r.1 - r.0).max().unwrap_or(0));
+    }
+}
+
+fn run(seed: u64, ops: usize) {
+    let mut rand = Rand(seed);
+    let mut defs = SlotDefs::<SLOTS>::new();
+    for slot in 0..SLOTS {
+        defs.set(slot,
+                 VirtAddr::new(BASE + slot * SLOT_BYTES),
+                 VirtAddr::new(BASE + (slot + 1) * SLOT_BYTES - 1));
+    }
+    let mut ca = CheapAlloc::<SLOTS, 32>::new();
+    ca.init_with_slotdefs(&defs);
+    let mut model = Model::new();
+
+    // Each slot has 8 regions separated by gaps.  Each region is split
+    // into up to 3 adjacent chunks, and chunks are added in random order.
+    let mut chunks: Vec<(usize, usize, usize)> = Vec::new();
+    for slot in 0..SLOTS {
+        for i in 0..8 {
+            let mut adr = BASE + slot * SLOT_BYTES + i * 0x2000 +
+                rand.below(0x10) * 8;
+            let end = adr + 0x100 + rand.below(0x1800);
+            for _ in 0..rand.below(3) {
+                let bytes = 1 + rand.below(end - adr);
+                if adr + bytes < end {
+                    chunks.push((slot, adr, bytes));
+                    adr += bytes;
+                }
+            }
+            chunks.push((slot, adr, end - adr));
+        }
+    }
+
+    for _ in 0..ops {
+        match rand.below(11) {
+            0 if !chunks.is_empty() => {
+                let (slot, adr, bytes) =
+                    chunks.swap_remove(rand.below(chunks.len()));
+                let spare = ca.spare_entries();
+                let r = ca.add_free_range(slot, VirtAddr::new(adr), bytes);
+                if spare > 0 {
+                    assert!(r.is_ok());
+                    model.add_free(slot, adr, adr + bytes);
+                    model.added[slot] += bytes;
+                } else {
+                    assert_eq!(r.err(), Some(no_entries()));
+                    chunks.push((slot, adr, bytes));
+                }
+            },
+            9 => {
+                // overlaps a free or used range.
+                let slot = rand.below(SLOTS);
+                let ranges: Vec<(usize, usize)> = model.free[slot].iter()
+                    .cloned()
+                    .chain(model.used.iter()
+                        .filter(|b| b.slot == slot)
+                        .map(|b| (b.adr, b.adr + b.bytes)))
+                    .collect();
+                if ranges.is_empty() {
+                    continue;
+                }
+                let (a, e) = ranges[rand.below(ranges.len())];
+                let slot_start = BASE + slot * SLOT_BYTES;
+                let slot_end = slot_start + SLOT_BYTES;
+                let adr = if rand.below(2) == 0 {
+                    a.saturating_sub(1 + rand.below(0x100)).max(slot_start)
+                } else {
+                    a + rand.below(e - a)
+                };
+                // the byte at max(a, adr) is in the range.
+                let end = (a.max(adr) + 1 + rand.below(0x100)).min(slot_end);
+                let r = ca.add_free_range(slot, VirtAddr::new(adr), end - adr);
+                assert_eq!(r.map_err(|e| e.code()), Err(Code::Inval));
+            },
+            1 => {
+                let slot = rand.below(SLOTS);
+                let adr = BASE + slot * SLOT_BYTES + rand.below(SLOT_BYTES);
+                let bytes = 1 + rand.below(0x200);
+                let forget = rand.below(4) == 0;
+                let spare = ca.spare_entries();
+                let r = ca.reserve(slot, VirtAddr::new(adr), bytes, forget);
+                let covered = model.free[slot].iter()
+                    .any(|&(a, e)| a <= adr && adr + bytes <= e);
+                match r {
+                    Ok(()) => {
+                        assert!(covered);
+                        assert!(!model.overlaps_used(adr, adr + bytes));
+                        model.remove_free(slot, adr, adr + bytes);
+                        if forget {
+                            model.forgotten[slot] += bytes;
+                        } else {
+                            model.used.push(Block { slot, adr, bytes });
+                        }
+                    },
+                    Err(e) if !covered => assert_eq!(e, Error::Busy),
+                    Err(e) => {
+                        // at most 2 entries are needed to split.
+                        assert!(spare < 2);
+                        assert_eq!(e, no_entries());
+                    },
+                }
+            },
+            2 ..= 5 => {
+                let layout = Layout::from_size_align(
+                    1 + rand.below(0x400), 1 << rand.below(9)).unwrap();
+                let slotmask = 1 + rand.below((1 << SLOTS) - 1);
+                let forget = rand.below(8) == 0;
+                let spare = ca.spare_entries();
+                // X is converted to a pointer at once.
+                match unsafe { ca.alloc::<u8>(slotmask, layout, forget) } {
+                    Ok(x) => {
+                        let adr = X::into_raw(x) as *mut u8 as usize;
+                        let end = adr + layout.size();
+                        assert_eq!(adr % layout.align(), 0);
+                        assert!(!model.overlaps_used(adr, end));
+                        let slot = model.covering_slot(adr, end)
+                            .expect("allocated memory is not free");
+                        assert!(slotmask & (1 << slot) != 0);
+                        model.remove_free(slot, adr, end);
+                        if forget {
+                            model.forgotten[slot] += layout.size();
+                        } else {
+                            model.used.push(
+                                Block { slot, adr, bytes: layout.size() });
+                        }
+                    },
+                    Err(Error::NoMemFor { bytes }) => {
+                        assert_eq!(bytes, layout.size());
+                        for slot in 0..SLOTS {
+                            if slotmask & (1 << slot) != 0 {
+                                assert!(!model.fits(slot, layout));
+                            }
+                        }
+                    },
+                    Err(e) => {
+                        assert!(spare < 2);
+                        assert_eq!(e, no_entries());
+                    },
+                }
+            },
+            6 ..= 8 if !model.used.is_empty() => {
+                let b = model.used.swap_remove(rand.below(model.used.len()));
+                assert!(ca.free(VirtAddr::new(b.adr)).is_ok());
+                model.add_free(b.slot, b.adr, b.adr + b.bytes);
+            },
+            _ => {
+                // not allocated.
+                let adr = BASE + rand.below(SLOTS * SLOT_BYTES);
+                if !model.used.iter().any(|b| b.adr == adr) {
+                    assert!(ca.free(VirtAddr::new(adr)).is_err());
+                }
+            },
+        }
+        check(&ca, &model);
+    }
+
+    // everything returns to the free ranges except forgotten memory.
+    while let Some(b) = model.used.pop() {
+        assert!(ca.free(VirtAddr::new(b.adr)).is_ok());
+        model.add_free(b.slot, b.adr, b.adr + b.bytes);
+        check(&ca, &model);
+    }
+}
+
+#[test]
+fn random_ops() {
+    for seed in 1..=64u64 {
+        run(seed.wrapping_mul(0x9e3779b97f4a7c15), 2000);
+    }
+}
+
+#[test]
+fn random_ops_long() {
+    run(0x1234_5678_9abc_def0, 50000);
+}
@@ -10,6 +10,7 @@ extern crate alloc;
 #[cfg(not(feature = "nobox"))]
 use alloc::boxed::Box;
 
+use core::cmp::Ordering;
 use core::convert::Into;
 use core::fmt;
 use core::marker::PhantomData;
@@ -17,6 +18,9 @@ use core::ops::DerefMut;
 use core::ops::Deref;
 use core::ptr::NonNull;
 
+use super::boxed::X;
+use super::zeroable::Zeroable;
+
 
 pub unsafe trait Refer<T: ?Sized>: DerefMut<Target = T> + Sized {
     fn into_ptr(self) -> *const T;
@@ -42,10 +46,28 @@ unsafe impl<T: ?Sized> Refer<T> for Box<T> {
     }
 }
 
+/// A raw pointer can't keep the owner, so only X which has the default
+/// owner can be linked.
+unsafe impl<T: ?Sized> Refer<T> for X<T> {
+    fn into_ptr(self) -> *const T {
+        assert!(X::has_default_owner(&self), "X without the default owner");
+        X::into_raw(self)
+    }
+    unsafe fn from_ptr(ptr: *const T) -> Self {
+        X::from_raw(ptr as *mut T)
+    }
+}
+
 pub struct RawRefer<T: ?Sized> {
     ptr: NonNull<T>,
 }
 impl<T: ?Sized> RawRefer<T> {
+    /// The caller keeps `ptr` alive while the list refers it.
+    pub fn new(ptr: &mut T) -> Self {
+        Self {
+            ptr: NonNull::from(ptr)
+        }
+    }
     pub fn as_mut<'t>(&mut self) -> &'t mut T {
         unsafe { &mut *self.ptr.as_ptr() }
     }
@@ -113,6 +135,8 @@ impl<Type: Clone> Clone for ForwardEnt<Type> {
 
 impl<Type: Copy> Copy for ForwardEnt<Type> {}
 
+unsafe impl<Type: Zeroable> Zeroable for ForwardEnt<Type> {}
+
 impl<Type> Deref for ForwardEnt<Type> {
     type Target = Type;
 
@@ -196,6 +220,8 @@ impl<Type: Clone> Clone for Ent<Type> {
 
 impl<Type: Copy> Copy for Ent<Type> {}
 
+unsafe impl<Type: Zeroable> Zeroable for Ent<Type> {}
+
 impl<Type> Deref for Ent<Type> {
     type Target = Type;
 
@@ -325,6 +351,9 @@ impl DualEnd {
     }
 }
 
+unsafe impl Zeroable for SingleEnd {}
+unsafe impl Zeroable for DualEnd {}
+
 // ListImpl
 
 pub struct ListImpl<Type, Ref, End, Entry> 
@@ -355,6 +384,13 @@ impl<Type, Ref, End, Entry> Clone for ListImpl<Type, Ref, End, Entry>
     }
 }
 
+/// A zeroed list is empty.
+unsafe impl<Type, Ref, End, Entry> Zeroable for ListImpl<Type, Ref, End, Entry>
+    where
+        Ref: Refer<Entry>,
+        End: EndBase + Zeroable,
+        Entry: EntBase<Type> {}
+
 /// implement for any ListImpl includes SingleForwardList.
 impl<'e, Type, Ref, End, Entry> ListImpl<Type, Ref, End, Entry>
     where
@@ -454,6 +490,128 @@ impl<'e, Type, Ref, End, Entry> ListImpl<Type, Ref, End, Entry>
         }
     }
 
+    /// Move the entries after `base` to a new list.
+    pub fn split_off(&mut self, base: &mut Entry) -> Self {
+        let mut other = Self::new();
+        let next = base.get_next();
+        if let Some(n) = Self::_ref_ent_or_none(next) {
+            n.set_prev(core::ptr::null());
+            other.end.set_head(next);
+            other.end.set_tail(self.end.get_tail_or_null());
+            base.set_next(core::ptr::null());
+            self.end.set_tail(base as *const Entry as *const Link);
+        }
+        other
+    }
+
+    /// Insert `ent` before the first entry greater than `ent`.
+    pub fn insert_sorted_by<F>(&mut self, ent: Ref, mut cmp: F)
+        where F: FnMut(&Type, &Type) -> Ordering
+    {
+        let mut prev: Option<&'e mut Entry> = None;
+        let mut cur = self.get_front();
+        while let Some(e) = cur {
+            if cmp(ent.ref_elem(), e.ref_elem()) == Ordering::Less {
+                break;
+            }
+            cur = self.get_next(e);
+            prev = Some(e);
+        }
+        match prev {
+            Some(p) => self.insert_after(p, ent),
+            None => self.push_front(ent),
+        }
+    }
+
+    /// Stable merge sort.
+    pub fn sort_by<F>(&mut self, mut cmp: F)
+        where F: FnMut(&Type, &Type) -> Ordering
+    {
+        let mut len = 0;
+        let mut ent = self.end.get_head();
+        while let Some(e) = Self::_ref_ent_or_none(ent) {
+            len += 1;
+            ent = e.get_next();
+        }
+
+        let mut rest = self.end.get_head();
+        let head = Self::_sort(&mut rest, len, &mut cmp);
+
+        // rebuild prev links and the tail.
+        let mut prev = core::ptr::null();
+        let mut ent = head;
+        while let Some(e) = Self::_ref_ent_or_none(ent) {
+            e.set_prev(prev);
+            prev = ent;
+            ent = e.get_next();
+        }
+        self.end.set_head(head);
+        self.end.set_tail(prev);
+    }
+
+    /// Sort `len` entries from `*head` and advance `*head` after them.
+    /// Only next links are valid in the result.
+    fn _sort<F>(head: &mut *const Link, len: usize, cmp: &mut F)
+        -> *const Link
+        where F: FnMut(&Type, &Type) -> Ordering
+    {
+        if len == 0 {
+            return core::ptr::null();
+        }
+        if len == 1 {
+            let ent = *head;
+            let e = Self::_ref_ent(ent);
+            *head = e.get_next();
+            e.set_next(core::ptr::null());
+            return ent;
+        }
+        let a = Self::_sort(head, len / 2, cmp);
+        let b = Self::_sort(head, len - len / 2, cmp);
+        Self::_merge(a, b, cmp)
+    }
+
+    fn _merge<F>(mut a: *const Link, mut b: *const Link, cmp: &mut F)
+        -> *const Link
+        where F: FnMut(&Type, &Type) -> Ordering
+    {
+        let mut head = core::ptr::null();
+        let mut tail: Option<&mut Entry> = None;
+        while !a.is_null() && !b.is_null() {
+            let ea = Self::_ref_ent(a);
+            let eb = Self::_ref_ent(b);
+            let ent = if cmp(ea.ref_elem(), eb.ref_elem()) != Ordering::Greater {
+                a = ea.get_next();
+                ea
+            } else {
+                b = eb.get_next();
+                eb
+            };
+            let ptr = ent as *const Entry as *const Link;
+            match tail {
+                Some(t) => t.set_next(ptr),
+                None => head = ptr,
+            }
+            tail = Some(ent);
+        }
+        let rest = if a.is_null() { b } else { a };
+        match tail {
+            Some(t) => t.set_next(rest),
+            None => head = rest,
+        }
+        head
+    }
+
+    pub fn cursor_front_mut(&mut self)
+        -> CursorMut<'_, Type, Ref, End, Entry>
+    {
+        let head = self.end.get_head();
+        CursorMut::<Type, Ref, End, Entry> {
+            list: self,
+            prev: core::ptr::null(),
+            cur: head,
+        }
+    }
+
     pub fn iter(&mut self) -> ListIter<'e, Type, Ref, End, Entry> {
         ListIter::<Type, Ref, End, Entry> {
             next: self.end.get_head(),
@@ -493,6 +651,50 @@ impl<'e, Type, Ref, Entry> ListImpl<Type, Ref, DualEnd, Entry>
     }
 }
 
+impl<Type, Ref, Entry> ListImpl<Type, Ref, DualEnd, Entry>
+    where
+        Ref: Refer<Entry>,
+        Entry: EntBase<Type> {
+
+    fn _take_all(other: &mut Self) -> (*const Link, *const Link) {
+        let r = (other.end.get_head(), other.end.get_tail());
+        other.end.set_head(core::ptr::null());
+        other.end.set_tail(core::ptr::null());
+        r
+    }
+
+    /// Move all entries of `other` to the back.
+    pub fn append(&mut self, other: &mut Self) {
+        let (head, tail) = Self::_take_all(other);
+        if head.is_null() {
+            return;
+        }
+        let old_tail = self.end.get_tail();
+        Self::_ref_ent(head).set_prev(old_tail);
+        match Self::_ref_ent_or_none(old_tail) {
+            Some(t) => t.set_next(head),
+            None => self.end.set_head(head),
+        }
+        self.end.set_tail(tail);
+    }
+
+    /// Move all entries of `other` after `base`.
+    pub fn splice_after(&mut self, base: &mut Entry, other: &mut Self) {
+        let (head, tail) = Self::_take_all(other);
+        if head.is_null() {
+            return;
+        }
+        let next = base.get_next();
+        Self::_ref_ent(head).set_prev(base as *const Entry as *const Link);
+        Self::_ref_ent(tail).set_next(next);
+        base.set_next(head);
+        match Self::_ref_ent_or_none(next) {
+            Some(n) => n.set_prev(tail),
+            None => self.end.set_tail(tail),
+        }
+    }
+}
+
 /// implement for SingleList or List.
 impl<'e, Type, Ref, End> ListImpl<Type, Ref, End, Ent<Type>>
     where
@@ -537,8 +739,23 @@ impl<'e, Type, Ref, End> ListImpl<Type, Ref, End, Ent<Type>>
 }
 
 /// implement for List.
-impl<'e, Type, Ref> ListImpl<Type, Ref, DualEnd, Ent<Type>>
+impl<Type, Ref> ListImpl<Type, Ref, DualEnd, Ent<Type>>
     where Ref: Refer<Ent<Type>> {
+    pub fn cursor_back_mut(&mut self)
+        -> CursorMut<'_, Type, Ref, DualEnd, Ent<Type>>
+    {
+        let tail = self.end.get_tail();
+        let prev = match Self::_ref_ent_or_none(tail) {
+            Some(t) => t.get_prev(),
+            None => core::ptr::null(),
+        };
+        CursorMut::<Type, Ref, DualEnd, Ent<Type>> {
+            list: self,
+            prev,
+            cur: tail,
+        }
+    }
+
     pub fn pop_back(&mut self) -> Option<Ref> {
         let old_tail = self.end.get_tail();
         if !old_tail.is_null() {
@@ -565,6 +782,188 @@ pub type ForwardList<Type, Ref> =
 pub type SingleForwardList<Type, Ref> =
     ListImpl<Type, Ref, SingleEnd, ForwardEnt<Type>>;
 
+// Cursor
+
+/// Cursor which can modify the list.
+/// The cursor points an entry or the "ghost" between the back and the front.
+pub struct CursorMut<'a, Type, Ref, End, Entry>
+    where
+        Ref: Refer<Entry>,
+        End: EndBase,
+        Entry: EntBase<Type> {
+    list: &'a mut ListImpl<Type, Ref, End, Entry>,
+    // Previous entry of cur.  The back entry if cur is the ghost.
+    prev: *const Link,
+    // Null if the ghost.
+    cur: *const Link,
+}
+
+/// implement for any list.
+impl<'a, Type, Ref, End, Entry> CursorMut<'a, Type, Ref, End, Entry>
+    where
+        Ref: Refer<Entry>,
+        End: EndBase,
+        Entry: EntBase<Type> + 'a {
+
+    fn _ref_ent_or_none(ent: *const Link) -> Option<&'a mut Entry> {
+        ListImpl::<Type, Ref, End, Entry>::_ref_ent_or_none(ent)
+    }
+
+    fn _next(&self) -> *const Link {
+        match Self::_ref_ent_or_none(self.cur) {
+            Some(cur) => cur.get_next(),
+            None => self.list.end.get_head(),
+        }
+    }
+
+    pub fn current(&mut self) -> Option<&mut Type> {
+        Self::_ref_ent_or_none(self.cur).map(|e| e.ref_elem_mut())
+    }
+
+    pub fn peek_next(&mut self) -> Option<&mut Type> {
+        Self::_ref_ent_or_none(self._next()).map(|e| e.ref_elem_mut())
+    }
+
+    pub fn peek_prev(&mut self) -> Option<&mut Type> {
+        Self::_ref_ent_or_none(self.prev).map(|e| e.ref_elem_mut())
+    }
+
+    /// Move to the next entry.  The ghost moves to the front.
+    pub fn move_next(&mut self) {
+        let next = self._next();
+        self.prev = if self.cur.is_null() {
+            core::ptr::null()
+        } else {
+            self.cur
+        };
+        self.cur = next;
+    }
+
+    /// Remove the current entry and move to the next entry.
+    pub fn remove_current(&mut self) -> Option<Ref> {
+        if self.cur.is_null() {
+            return None;
+        }
+        let next = self._next();
+        let r = match Self::_ref_ent_or_none(self.prev) {
+            Some(prev) => self.list.remove_next(prev),
+            None => self.list.pop_front(),
+        };
+        self.cur = next;
+        r
+    }
+
+    /// Insert after the current entry.  The ghost inserts to the front.
+    pub fn insert_after(&mut self, ent: Ref) {
+        match Self::_ref_ent_or_none(self.cur) {
+            Some(cur) => self.list.insert_after(cur, ent),
+            None => {
+                let was_empty = self.list.end.get_head().is_null();
+                self.list.push_front(ent);
+                if was_empty {
+                    self.prev = self.list.end.get_head();
+                }
+            },
+        }
+    }
+
+    /// Insert before the current entry.  The ghost inserts to the back.
+    pub fn insert_before(&mut self, ent: Ref) {
+        self.prev = match Self::_ref_ent_or_none(self.prev) {
+            Some(prev) => {
+                self.list.insert_after(prev, ent);
+                prev.get_next()
+            },
+            None => {
+                self.list.push_front(ent);
+                self.list.end.get_head()
+            },
+        };
+    }
+
+    /// Move the entries after the current entry to a new list.
+    /// The ghost moves all entries.
+    pub fn split_after(&mut self) -> ListImpl<Type, Ref, End, Entry> {
+        let mut other = ListImpl::<Type, Ref, End, Entry>::new();
+        let next = self._next();
+        if next.is_null() {
+            return other;
+        }
+        other.end.set_head(next);
+        other.end.set_tail(self.list.end.get_tail_or_null());
+        Self::_ref_ent_or_none(next).unwrap().set_prev(core::ptr::null());
+
+        match Self::_ref_ent_or_none(self.cur) {
+            Some(cur) => {
+                cur.set_next(core::ptr::null());
+                self.list.end.set_tail(self.cur);
+            },
+            None => {
+                self.list.end.set_head(core::ptr::null());
+                self.list.end.set_tail(core::ptr::null());
+                self.prev = core::ptr::null();
+            },
+        }
+        other
+    }
+
+    /// Move the entries before the current entry to a new list.
+    /// The ghost moves all entries.
+    pub fn split_before(&mut self) -> ListImpl<Type, Ref, End, Entry> {
+        let mut other = ListImpl::<Type, Ref, End, Entry>::new();
+        let prev = match Self::_ref_ent_or_none(self.prev) {
+            Some(prev) => prev,
+            None => return other,
+        };
+        other.end.set_head(self.list.end.get_head());
+        other.end.set_tail(self.prev);
+        prev.set_next(core::ptr::null());
+
+        self.list.end.set_head(self.cur);
+        match Self::_ref_ent_or_none(self.cur) {
+            Some(cur) => cur.set_prev(core::ptr::null()),
+            None => self.list.end.set_tail(core::ptr::null()),
+        }
+        self.prev = core::ptr::null();
+        other
+    }
+}
+
+/// implement for SingleList or List.
+impl<'a, Type, Ref, End> CursorMut<'a, Type, Ref, End, Ent<Type>>
+    where
+        Ref: Refer<Ent<Type>>,
+        End: EndBase,
+        Type: 'a {
+
+    /// Back entry.  SingleList walks the whole list.
+    fn _back(&self) -> *const Link {
+        let mut back = self.list.end.get_tail_or_null();
+        if back.is_null() {
+            let mut ent = self.list.end.get_head();
+            while let Some(e) = Self::_ref_ent_or_none(ent) {
+                back = ent;
+                ent = e.get_next();
+            }
+        }
+        back
+    }
+
+    /// Move to the previous entry.  The ghost moves to the back.
+    pub fn move_prev(&mut self) {
+        if !self.cur.is_null() && self.prev.is_null() {
+            self.cur = core::ptr::null();
+            self.prev = self._back();
+        } else {
+            self.cur = self.prev;
+            self.prev = match Self::_ref_ent_or_none(self.cur) {
+                Some(cur) => cur.get_prev(),
+                None => core::ptr::null(),
+            };
+        }
+    }
+}
+
 // Iterator
 
 pub struct ListIter<'e, Type, Ref, End, Entry> 
@@ -633,12 +1032,18 @@ impl<'e, Type, Ref> DoubleEndedIterator
 
 #[cfg(test)]
 mod test {
+    extern crate std;
+    use std::vec::Vec;
     use super::*;
 
     #[derive(Clone, Copy, Debug, PartialEq)]
     struct Data {
         data: isize,
     }
+
+    /// (front, back) of a split list.
+    type SplitLists<Ref, End, Entry> =
+        (ListImpl<Data, Ref, End, Entry>, ListImpl<Data, Ref, End, Entry>);
     impl AsRef<Ent<Data>> for Ent<Data> {
         fn as_ref(&self) -> &Ent<Data> { &self }
     }
@@ -648,6 +1053,9 @@ mod test {
     impl<T> AsRef<T> for RawRefer<T> {
         fn as_ref(&self) -> &T { self.deref() }
     }
+    impl<T> AsRef<T> for X<T> {
+        fn as_ref(&self) -> &T { self.deref() }
+    }
 
     fn test_pop_front<Ref>(
         list: &mut List<Data, Ref>,
@@ -1028,6 +1436,329 @@ mod test {
         assert_eq!(list.get_front(), None);
     }
 
+    fn values<Ref, End, Entry>(list: &mut ListImpl<Data, Ref, End, Entry>)
+        -> Vec<isize>
+    where
+        Ref: Refer<Entry>,
+        End: EndBase,
+        Entry: EntBase<Data>,
+    {
+        list.iter().map(|x| x.data).collect()
+    }
+
+    /// Returns (list, front, back) of split lists.
+    fn test_cursor<Ref, End, Entry, F>(
+        list: &mut ListImpl<Data, Ref, End, Entry>,
+        mut new: F)
+        -> SplitLists<Ref, End, Entry>
+    where
+        Ref: Refer<Entry>,
+        End: EndBase,
+        Entry: EntBase<Data>,
+        F: FnMut(isize) -> Ref,
+    {
+        {
+            let mut c = list.cursor_front_mut();
+            assert!(c.current().is_none());
+            assert!(c.remove_current().is_none());
+            // the ghost appends.
+            for i in 1..=5 {
+                c.insert_before(new(i));
+            }
+            assert!(c.current().is_none());
+        }
+        assert_eq!(values(list), [1, 2, 3, 4, 5]);
+
+        let mut c = list.cursor_front_mut();
+        assert_eq!(c.current().unwrap().data, 1);
+        assert!(c.peek_prev().is_none());
+        c.move_next();
+        assert_eq!(c.remove_current().unwrap().data, 2);
+        // list: 1, 3, 4, 5
+        assert_eq!(c.current().unwrap().data, 3);
+        assert_eq!(c.peek_prev().unwrap().data, 1);
+        assert_eq!(c.peek_next().unwrap().data, 4);
+        c.insert_before(new(6));
+        c.insert_after(new(7));
+        // list: 1, 6, 3, 7, 4, 5
+        assert_eq!(c.peek_prev().unwrap().data, 6);
+        assert_eq!(c.peek_next().unwrap().data, 7);
+        c.move_next();
+        c.move_next();
+        c.move_next();
+        assert_eq!(c.remove_current().unwrap().data, 5);
+        // list: 1, 6, 3, 7, 4
+        assert!(c.current().is_none());
+        assert_eq!(c.peek_prev().unwrap().data, 4);
+        assert_eq!(c.peek_next().unwrap().data, 1);
+        c.insert_before(new(8));
+        c.insert_after(new(9));
+        // list: 9, 1, 6, 3, 7, 4, 8
+        assert_eq!(c.peek_prev().unwrap().data, 8);
+        c.move_next();
+        assert_eq!(c.remove_current().unwrap().data, 9);
+        // list: 1, 6, 3, 7, 4, 8
+        assert!(c.peek_prev().is_none());
+        c.move_next();
+        c.move_next();
+        assert_eq!(c.current().unwrap().data, 3);
+        let back = c.split_after();
+        let front = c.split_before();
+        assert_eq!(c.current().unwrap().data, 3);
+        assert!(c.peek_prev().is_none());
+        assert!(c.peek_next().is_none());
+        c.move_next();
+        assert!(c.current().is_none());
+        assert_eq!(c.peek_prev().unwrap().data, 3);
+        // the ghost moves all.
+        let mut all = c.split_before();
+        assert!(list.get_front().is_none());
+        assert_eq!(values(&mut all), [3]);
+        list.push_front(all.pop_front().unwrap());
+
+        let mut c = list.cursor_front_mut();
+        c.move_next();
+        let mut all = c.split_after();
+        assert!(c.peek_prev().is_none());
+        assert!(list.get_front().is_none());
+        assert_eq!(values(&mut all), [3]);
+        list.push_front(all.pop_front().unwrap());
+        assert_eq!(values(list), [3]);
+
+        (front, back)
+    }
+
+    fn test_cursor_prev<Ref, End, F>(
+        list: &mut ListImpl<Data, Ref, End, Ent<Data>>,
+        mut new: F)
+    where
+        Ref: Refer<Ent<Data>>,
+        End: EndBase,
+        F: FnMut(isize) -> Ref,
+    {
+        let (mut front, mut back) = test_cursor(list, &mut new);
+        assert_eq!(values(&mut front), [1, 6]);
+        assert_eq!(values(&mut back), [7, 4, 8]);
+
+        let mut c = back.cursor_front_mut();
+        c.move_prev();
+        assert!(c.current().is_none());
+        assert_eq!(c.peek_prev().unwrap().data, 8);
+        c.move_prev();
+        assert_eq!(c.current().unwrap().data, 8);
+        c.move_prev();
+        assert_eq!(c.remove_current().unwrap().data, 4);
+        assert_eq!(c.peek_prev().unwrap().data, 7);
+        c.move_prev();
+        c.move_prev();
+        c.move_prev();
+        assert_eq!(c.current().unwrap().data, 8);
+        c.insert_after(new(10));
+        c.move_next();
+        c.move_next();
+        // the ghost
+        c.move_prev();
+        assert_eq!(c.current().unwrap().data, 10);
+        assert_eq!(values(&mut back), [7, 8, 10]);
+    }
+
+    fn test_splice<Ref, Entry, F>(
+        list: &mut ListImpl<Data, Ref, DualEnd, Entry>,
+        mut new: F)
+    where
+        Ref: Refer<Entry>,
+        Entry: EntBase<Data>,
+        F: FnMut(isize) -> Ref,
+    {
+        let mut other = ListImpl::<Data, Ref, DualEnd, Entry>::new();
+        list.append(&mut other);
+        assert!(list.get_front().is_none());
+        other.push_back(new(1));
+        other.push_back(new(2));
+        list.append(&mut other);
+        // list: 1, 2
+        assert!(other.get_front().is_none());
+        assert!(other.get_back().is_none());
+        assert_eq!(list.get_back().unwrap().data, 2);
+        other.push_back(new(3));
+        other.push_back(new(4));
+        list.append(&mut other);
+        // list: 1, 2, 3, 4
+        assert_eq!(values(list), [1, 2, 3, 4]);
+        assert_eq!(list.get_back().unwrap().data, 4);
+
+        other.push_back(new(5));
+        other.push_back(new(6));
+        list.splice_after(list.get_front().unwrap(), &mut other);
+        // list: 1, 5, 6, 2, 3, 4
+        assert!(other.get_front().is_none());
+        list.splice_after(list.get_back().unwrap(), &mut other);
+        other.push_back(new(7));
+        list.splice_after(list.get_back().unwrap(), &mut other);
+        // list: 1, 5, 6, 2, 3, 4, 7
+        assert_eq!(values(list), [1, 5, 6, 2, 3, 4, 7]);
+        assert_eq!(list.get_back().unwrap().data, 7);
+
+        let base = list.get_next(list.get_front().unwrap()).unwrap();
+        let mut back = list.split_off(base);
+        // list: 1, 5, back: 6, 2, 3, 4, 7
+        assert_eq!(list.get_back().unwrap().data, 5);
+        assert_eq!(back.get_back().unwrap().data, 7);
+        let back2 = back.split_off(back.get_back().unwrap());
+        assert!(back2.get_front().is_none());
+        assert!(back2.get_back().is_none());
+        let mut back2 = back.split_off(back.get_front().unwrap());
+        // back: 6, back2: 2, 3, 4, 7
+        assert_eq!(back.get_back().unwrap().data, 6);
+        list.append(&mut back2);
+        list.append(&mut back);
+        assert_eq!(values(list), [1, 5, 2, 3, 4, 7, 6]);
+        assert_eq!(list.get_back().unwrap().data, 6);
+        while list.pop_front().is_some() {}
+    }
+
+    fn test_sort<Ref, End, Entry, F>(
+        list: &mut ListImpl<Data, Ref, End, Entry>,
+        mut new: F)
+    where
+        Ref: Refer<Entry>,
+        End: EndBase,
+        Entry: EntBase<Data>,
+        F: FnMut(isize) -> Ref,
+    {
+        let cmp = |a: &Data, b: &Data| a.data.cmp(&b.data);
+        list.sort_by(cmp);
+        assert!(list.get_front().is_none());
+        list.insert_sorted_by(new(5), cmp);
+        list.insert_sorted_by(new(2), cmp);
+        list.insert_sorted_by(new(8), cmp);
+        list.insert_sorted_by(new(5), cmp);
+        list.insert_sorted_by(new(1), cmp);
+        assert_eq!(values(list), [1, 2, 5, 5, 8]);
+        while list.pop_front().is_some() {}
+
+        for &i in [7, 3, 9, 3, 1, 8, 2, 6, 0, 5, 4].iter() {
+            list.push_front(new(i));
+        }
+        list.sort_by(cmp);
+        assert_eq!(values(list), [0, 1, 2, 3, 3, 4, 5, 6, 7, 8, 9]);
+        // stable: sorting by parity keeps the order.
+        list.sort_by(|a, b| (a.data % 2).cmp(&(b.data % 2)));
+        assert_eq!(values(list), [0, 2, 4, 6, 8, 1, 3, 3, 5, 7, 9]);
+        list.insert_sorted_by(new(10), |a, b| (a.data % 2).cmp(&(b.data % 2)));
+        assert_eq!(values(list), [0, 2, 4, 6, 8, 10, 1, 3, 3, 5, 7, 9]);
+        while list.pop_front().is_some() {}
+    }
+
+    #[test]
+    fn test_splice_sort() {
+        // entries are taken from `val` in order.
+        let mut val: Vec<Ent<Data>> = Vec::with_capacity(64);
+        let p = val.as_mut_ptr();
+        let mut n = 0;
+        let mut new = |data| unsafe {
+            let e = p.add(n);
+            n += 1;
+            e.write(Ent::new(Data { data }));
+            &mut *e
+        };
+        let mut list = List::<Data, &mut Ent<Data>>::new();
+        test_splice(&mut list, &mut new);
+        test_sort(&mut list, &mut new);
+        let mut list = SingleList::<Data, &mut Ent<Data>>::new();
+        test_sort(&mut list, &mut new);
+
+        let mut list = List::<Data, Box<Ent<Data>>>::new();
+        test_splice(&mut list, |data| Box::new(Ent::new(Data { data })));
+        // prev links are rebuilt.
+        for &i in [3, 1, 2].iter() {
+            list.push_back(Box::new(Ent::new(Data { data: i })));
+        }
+        list.sort_by(|a, b| a.data.cmp(&b.data));
+        let mut iter = list.iter().rev();
+        for x in [3, 2, 1].iter() {
+            assert_eq!(iter.next(), Some(&mut Data { data: *x }));
+        }
+        assert_eq!(iter.next(), None);
+        while list.pop_back().is_some() {}
+
+        let mut val: Vec<ForwardEnt<Data>> = Vec::with_capacity(64);
+        let p = val.as_mut_ptr();
+        let mut n = 0;
+        let mut new = |data| unsafe {
+            let e = p.add(n);
+            n += 1;
+            e.write(ForwardEnt::new(Data { data }));
+            (&mut *e).into()
+        };
+        let mut list = ForwardList::<Data, RawRefer<ForwardEnt<Data>>>::new();
+        test_splice(&mut list, &mut new);
+        test_sort(&mut list, &mut new);
+        let mut list =
+            SingleForwardList::<Data, Box<ForwardEnt<Data>>>::new();
+        test_sort(&mut list, |data| Box::new(ForwardEnt::new(Data { data })));
+    }
+
+    #[test]
+    fn test_cursor_ref() {
+        let mut val: Vec<Ent<Data>> =
+            (0..16).map(|data| Ent::new(Data { data })).collect();
+        let p = val.as_mut_ptr();
+        let mut list = List::<Data, &mut Ent<Data>>::new();
+        test_cursor_prev(&mut list, |i| unsafe { &mut *p.add(i as usize) });
+        assert_eq!(list.get_back().unwrap().data, 3);
+    }
+
+    #[test]
+    fn test_cursor_box() {
+        let new = |data| Box::new(Ent::new(Data { data }));
+        let mut list = List::<Data, Box<Ent<Data>>>::new();
+        test_cursor_prev(&mut list, new);
+        assert_eq!(list.get_back().unwrap().data, 3);
+
+        let mut c = list.cursor_back_mut();
+        assert_eq!(c.current().unwrap().data, 3);
+        c.insert_before(new(1));
+        c.insert_before(new(2));
+        let mut c = list.cursor_back_mut();
+        assert_eq!(c.peek_prev().unwrap().data, 2);
+        c.move_prev();
+        c.move_prev();
+        assert!(c.peek_prev().is_none());
+        assert_eq!(values(&mut list), [1, 2, 3]);
+
+        let mut list = SingleList::<Data, Box<Ent<Data>>>::new();
+        test_cursor_prev(&mut list, new);
+
+        let new = |data| Box::new(ForwardEnt::new(Data { data }));
+        let mut list = ForwardList::<Data, Box<ForwardEnt<Data>>>::new();
+        let (mut front, mut back) = test_cursor(&mut list, new);
+        assert_eq!(list.get_back().unwrap().data, 3);
+        assert_eq!(front.get_back().unwrap().data, 6);
+        assert_eq!(back.get_back().unwrap().data, 8);
+        assert_eq!(values(&mut front), [1, 6]);
+        assert_eq!(values(&mut back), [7, 4, 8]);
+
+        let mut list =
+            SingleForwardList::<Data, Box<ForwardEnt<Data>>>::new();
+        let (mut front, mut back) = test_cursor(&mut list, new);
+        assert_eq!(values(&mut front), [1, 6]);
+        assert_eq!(values(&mut back), [7, 4, 8]);
+    }
+
+    #[test]
+    fn test_cursor_raw() {
+        let mut val: Vec<ForwardEnt<Data>> =
+            (0..16).map(|data| ForwardEnt::new(Data { data })).collect();
+        let p = val.as_mut_ptr();
+        let mut list =
+            SingleForwardList::<Data, RawRefer<ForwardEnt<Data>>>::new();
+        let (mut front, mut back) = test_cursor(
+            &mut list, |i| unsafe { (&mut *p.add(i as usize)).into() });
+        assert_eq!(values(&mut front), [1, 6]);
+        assert_eq!(values(&mut back), [7, 4, 8]);
+    }
+
     #[test]
     fn test_ref() {
         let mut list = List::<Data, &mut Ent<Data>>::new();
@@ -1077,6 +1808,70 @@ mod test {
         test_remove(&mut list, val1, val2, val3, val4);
     }
 
+    #[test]
+    fn test_x() {
+        use core::alloc::Layout;
+        use super::super::boxed::ANY_SLOT;
+        use super::super::cheap_alloc::*;
+        use super::super::ops::VirtAddr;
+
+        let mut defs = SlotDefs::new();
+        defs.set(0, VirtAddr::new(0), VirtAddr::new(usize::MAX));
+        let mut ca: CheapAlloc = CheapAlloc::new();
+        ca.init_with_slotdefs(&defs);
+        let mut mem = [0u64; 0x100];
+        let start = VirtAddr::from_ptr(mem.as_mut_ptr());
+        ca.add_free_range(0, start, 0x800).unwrap();
+
+        // no default owner is set, so X without an owner can be linked.
+        // it does not free the memory on drop.
+        let mut new_x = |data| {
+            let x = unsafe {
+                ca.alloc::<Ent<Data>>(0x1, Layout::new::<Ent<Data>>(), false)
+            }.unwrap();
+            let x = X::into_raw(X::write(x, Ent::new(Data { data })));
+            unsafe { X::from_raw_in(x, None, ANY_SLOT) }
+        };
+
+        let mut list = List::<Data, X<Ent<Data>>>::new();
+        test_pop_front(&mut list, new_x(1), new_x(2), new_x(3), new_x(4));
+        test_pop_back(&mut list, new_x(1), new_x(2), new_x(3), new_x(4));
+        test_remove(&mut list, new_x(1), new_x(2), new_x(3), new_x(4));
+
+        // entries are left allocated.
+        let used: std::vec::Vec<_> = ca.used_ranges(0).collect();
+        assert_eq!(used.len(), 12);
+        for (adr, _) in used {
+            assert!(ca.free(adr).is_ok());
+        }
+        let layout = Layout::new::<[u64; 0x100]>();
+        let x = unsafe { ca.alloc::<u8>(0x1, layout, false) };
+        assert!(x.is_ok());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_x_owner() {
+        use core::alloc::Layout;
+        use super::super::cheap_alloc::*;
+        use super::super::ops::VirtAddr;
+
+        let mut defs = SlotDefs::new();
+        defs.set(0, VirtAddr::new(0), VirtAddr::new(usize::MAX));
+        let mut ca: CheapAlloc = CheapAlloc::new();
+        ca.init_with_slotdefs(&defs);
+        let mut mem = [0u64; 0x10];
+        let start = VirtAddr::from_ptr(mem.as_mut_ptr());
+        ca.add_free_range(0, start, 0x80).unwrap();
+
+        // X owned by `ca` can't be linked.
+        let x = unsafe {
+            ca.alloc::<Ent<Data>>(0x1, Layout::new::<Ent<Data>>(), false)
+        }.unwrap();
+        let mut list = List::<Data, X<Ent<Data>>>::new();
+        list.push_front(X::write(x, Ent::new(Data { data: 1 })));
+    }
+
     #[test]
     fn test_raw() {
         let mut list = List::<Data, RawRefer<Ent<Data>>>::new();
@@ -0,0 +1,650 @@
+// Uniqos  --  Unique Operating System
+// (c) 2019 KATO Takeshi
+// Released under the MIT license
+
+//! Original AVL tree.
+//! The entry wraps the element as cheap_list::Ent does.
+
+use core::cmp::Ordering;
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::DerefMut;
+use core::ops::Deref;
+
+use super::cheap_list::{Link, RawRefer, Refer};
+use super::zeroable::Zeroable;
+
+
+// TreeEnt
+
+pub struct TreeEnt<Type> {
+    parent: *const Link,
+    left: *const Link,
+    right: *const Link,
+    height: usize,
+    elem: Type
+}
+
+impl<Type> TreeEnt<Type> {
+    pub fn new(elem: Type) -> Self {
+        Self {
+            parent: core::ptr::null(),
+            left: core::ptr::null(),
+            right: core::ptr::null(),
+            height: 0,
+            elem,
+        }
+    }
+
+    pub fn ref_elem(&self) -> &Type {
+        &self.elem
+    }
+
+    pub fn ref_elem_mut(&mut self) -> &mut Type {
+        &mut self.elem
+    }
+}
+
+impl<Type: Clone> Clone for TreeEnt<Type> {
+    fn clone(&self) -> Self {
+        Self::new(self.elem.clone())
+    }
+}
+
+unsafe impl<Type: Zeroable> Zeroable for TreeEnt<Type> {}
+
+impl<Type> Deref for TreeEnt<Type> {
+    type Target = Type;
+
+    fn deref(&self) -> &Self::Target {
+        self.ref_elem()
+    }
+}
+
+impl<Type> DerefMut for TreeEnt<Type> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.ref_elem_mut()
+    }
+}
+
+impl<Type: PartialEq> PartialEq for TreeEnt<Type> {
+    fn eq(&self, other: &Self) -> bool {
+        self.elem == other.elem
+    }
+}
+
+impl<Type: fmt::Debug> fmt::Debug for TreeEnt<Type> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TreeEnt {{ parent: {:?}, left: {:?}, right: {:?}, ",
+               self.parent, self.left, self.right)?;
+        self.elem.fmt(f)?;
+        write!(f, "}}")
+    }
+}
+
+impl<Type> From<&mut TreeEnt<Type>> for RawRefer<TreeEnt<Type>> {
+    fn from(ent: &mut TreeEnt<Type>) -> Self {
+        RawRefer::new(ent)
+    }
+}
+
+// Tree
+
+/// Entries which compare equal are kept in the inserted order.
+pub struct Tree<Type, Ref>
+    where Ref: Refer<TreeEnt<Type>> {
+    root: *const Link,
+
+    _phantom1: PhantomData<Type>,
+    _phantom2: PhantomData<Ref>,
+}
+
+/// A zeroed tree is empty.
+unsafe impl<Type, Ref> Zeroable for Tree<Type, Ref>
+    where Ref: Refer<TreeEnt<Type>> {}
+
+impl<Type, Ref> Default for Tree<Type, Ref>
+    where Ref: Refer<TreeEnt<Type>> {
+
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'e, Type, Ref> Tree<Type, Ref>
+    where
+        Ref: Refer<TreeEnt<Type>>,
+        Type: 'e {
+
+    pub fn new() -> Self {
+        Self {
+            root: core::ptr::null(),
+            _phantom1: PhantomData,
+            _phantom2: PhantomData,
+        }
+    }
+
+    fn _ref_ent<'t>(ent: *const Link) -> &'t mut TreeEnt<Type> {
+        unsafe { &mut *(ent as *mut TreeEnt<Type>) }
+    }
+
+    fn _ref_ent_or_none<'t>(ent: *const Link) -> Option<&'t mut TreeEnt<Type>> {
+        unsafe { (ent as *mut TreeEnt<Type>).as_mut() }
+    }
+
+    fn _link(ent: &TreeEnt<Type>) -> *const Link {
+        ent as *const TreeEnt<Type> as *const Link
+    }
+
+    fn _height(ent: *const Link) -> usize {
+        match Self::_ref_ent_or_none(ent) {
+            Some(e) => e.height,
+            None => 0,
+        }
+    }
+
+    fn _update_height(ent: &mut TreeEnt<Type>) {
+        ent.height =
+            1 + core::cmp::max(Self::_height(ent.left), Self::_height(ent.right));
+    }
+
+    fn _leftmost(mut ent: *const Link) -> *const Link {
+        while let Some(e) = Self::_ref_ent_or_none(ent) {
+            if e.left.is_null() {
+                break;
+            }
+            ent = e.left;
+        }
+        ent
+    }
+
+    fn _rightmost(mut ent: *const Link) -> *const Link {
+        while let Some(e) = Self::_ref_ent_or_none(ent) {
+            if e.right.is_null() {
+                break;
+            }
+            ent = e.right;
+        }
+        ent
+    }
+
+    /// Replace `old` child of `parent` (or the root) with `new`.
+    fn _replace_child(
+        &mut self, parent: *const Link, old: *const Link, new: *const Link)
+    {
+        match Self::_ref_ent_or_none(parent) {
+            None => self.root = new,
+            Some(p) if p.left == old => p.left = new,
+            Some(p) => p.right = new,
+        }
+    }
+
+    fn _rotate_left(&mut self, x: *const Link) -> *const Link {
+        let xe = Self::_ref_ent(x);
+        let y = xe.right;
+        let ye = Self::_ref_ent(y);
+        xe.right = ye.left;
+        if let Some(b) = Self::_ref_ent_or_none(ye.left) {
+            b.parent = x;
+        }
+        ye.parent = xe.parent;
+        self._replace_child(xe.parent, x, y);
+        ye.left = x;
+        xe.parent = y;
+        Self::_update_height(xe);
+        Self::_update_height(ye);
+        y
+    }
+
+    fn _rotate_right(&mut self, x: *const Link) -> *const Link {
+        let xe = Self::_ref_ent(x);
+        let y = xe.left;
+        let ye = Self::_ref_ent(y);
+        xe.left = ye.right;
+        if let Some(b) = Self::_ref_ent_or_none(ye.right) {
+            b.parent = x;
+        }
+        ye.parent = xe.parent;
+        self._replace_child(xe.parent, x, y);
+        ye.right = x;
+        xe.parent = y;
+        Self::_update_height(xe);
+        Self::_update_height(ye);
+        y
+    }
+
+    /// Fix heights and balance from `ent` to the root.
+    fn _rebalance(&mut self, mut ent: *const Link) {
+        while let Some(e) = Self::_ref_ent_or_none(ent) {
+            Self::_update_height(e);
+            let lh = Self::_height(e.left);
+            let rh = Self::_height(e.right);
+            if lh > rh + 1 {
+                let l = Self::_ref_ent(e.left);
+                if Self::_height(l.left) < Self::_height(l.right) {
+                    self._rotate_left(e.left);
+                }
+                ent = self._rotate_right(ent);
+            } else if rh > lh + 1 {
+                let r = Self::_ref_ent(e.right);
+                if Self::_height(r.right) < Self::_height(r.left) {
+                    self._rotate_right(e.right);
+                }
+                ent = self._rotate_left(ent);
+            }
+            ent = Self::_ref_ent(ent).parent;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_null()
+    }
+
+    pub fn get_first(&self) -> Option<&'e mut TreeEnt<Type>> {
+        Self::_ref_ent_or_none(Self::_leftmost(self.root))
+    }
+
+    pub fn get_last(&self) -> Option<&'e mut TreeEnt<Type>> {
+        Self::_ref_ent_or_none(Self::_rightmost(self.root))
+    }
+
+    fn _next(ent: *const Link) -> *const Link {
+        let e = Self::_ref_ent(ent);
+        if !e.right.is_null() {
+            return Self::_leftmost(e.right);
+        }
+        let mut ent = ent;
+        let mut parent = e.parent;
+        while let Some(p) = Self::_ref_ent_or_none(parent) {
+            if p.left == ent {
+                break;
+            }
+            ent = parent;
+            parent = p.parent;
+        }
+        parent
+    }
+
+    pub fn get_next(&self, base: &TreeEnt<Type>) -> Option<&'e mut TreeEnt<Type>> {
+        Self::_ref_ent_or_none(Self::_next(Self::_link(base)))
+    }
+
+    pub fn get_prev(&self, base: &TreeEnt<Type>) -> Option<&'e mut TreeEnt<Type>> {
+        if !base.left.is_null() {
+            return Self::_ref_ent_or_none(Self::_rightmost(base.left));
+        }
+        let mut ent = Self::_link(base);
+        let mut parent = base.parent;
+        while let Some(p) = Self::_ref_ent_or_none(parent) {
+            if p.right == ent {
+                return Some(p);
+            }
+            ent = parent;
+            parent = p.parent;
+        }
+        None
+    }
+
+    /// Insert after the entries which are not greater than `ent`.
+    pub fn insert_by<F>(&mut self, ent: Ref, mut cmp: F)
+        where F: FnMut(&Type, &Type) -> Ordering
+    {
+        let new_ent = ent.into_ptr() as *const Link;
+        let ne = Self::_ref_ent(new_ent);
+
+        let mut parent = core::ptr::null();
+        let mut left = false;
+        let mut cur = self.root;
+        while let Some(c) = Self::_ref_ent_or_none(cur) {
+            parent = cur;
+            left = cmp(&ne.elem, &c.elem) == Ordering::Less;
+            cur = if left { c.left } else { c.right };
+        }
+
+        ne.parent = parent;
+        ne.left = core::ptr::null();
+        ne.right = core::ptr::null();
+        ne.height = 1;
+        match Self::_ref_ent_or_none(parent) {
+            None => self.root = new_ent,
+            Some(p) if left => p.left = new_ent,
+            Some(p) => p.right = new_ent,
+        }
+        self._rebalance(parent);
+    }
+
+    pub fn insert(&mut self, ent: Ref)
+        where Type: Ord
+    {
+        self.insert_by(ent, |a, b| a.cmp(b))
+    }
+
+    pub fn remove(&mut self, target: &TreeEnt<Type>) -> Ref {
+        let z = Self::_link(target);
+        let ze = Self::_ref_ent(z);
+
+        if ze.left.is_null() || ze.right.is_null() {
+            let child = if ze.left.is_null() { ze.right } else { ze.left };
+            if let Some(c) = Self::_ref_ent_or_none(child) {
+                c.parent = ze.parent;
+            }
+            self._replace_child(ze.parent, z, child);
+            self._rebalance(ze.parent);
+        } else {
+            // replace with the successor.
+            let y = Self::_leftmost(ze.right);
+            let ye = Self::_ref_ent(y);
+            let fix_from = if ye.parent != z {
+                let yp = ye.parent;
+                Self::_ref_ent(yp).left = ye.right;
+                if let Some(r) = Self::_ref_ent_or_none(ye.right) {
+                    r.parent = yp;
+                }
+                ye.right = ze.right;
+                Self::_ref_ent(ze.right).parent = y;
+                yp
+            } else {
+                y
+            };
+            ye.left = ze.left;
+            Self::_ref_ent(ze.left).parent = y;
+            ye.parent = ze.parent;
+            self._replace_child(ze.parent, z, y);
+            ye.height = ze.height;
+            self._rebalance(fix_from);
+        }
+
+        ze.parent = core::ptr::null();
+        ze.left = core::ptr::null();
+        ze.right = core::ptr::null();
+        unsafe { Refer::from_ptr(target) }
+    }
+
+    pub fn pop_first(&mut self) -> Option<Ref> {
+        self.get_first().map(|e| self.remove(e))
+    }
+
+    /// `f` returns the order of the element to the key.
+    /// Any one of the equal entries is returned.
+    pub fn find_by<F>(&self, mut f: F) -> Option<&'e mut TreeEnt<Type>>
+        where F: FnMut(&Type) -> Ordering
+    {
+        let mut cur = self.root;
+        while let Some(c) = Self::_ref_ent_or_none(cur) {
+            match f(&c.elem) {
+                Ordering::Less => cur = c.right,
+                Ordering::Greater => cur = c.left,
+                Ordering::Equal => return Some(c),
+            }
+        }
+        None
+    }
+
+    /// The first entry which is not less than the key.
+    pub fn lower_bound_by<F>(&self, mut f: F) -> Option<&'e mut TreeEnt<Type>>
+        where F: FnMut(&Type) -> Ordering
+    {
+        let mut ret = core::ptr::null();
+        let mut cur = self.root;
+        while let Some(c) = Self::_ref_ent_or_none(cur) {
+            if f(&c.elem) == Ordering::Less {
+                cur = c.right;
+            } else {
+                ret = cur;
+                cur = c.left;
+            }
+        }
+        Self::_ref_ent_or_none(ret)
+    }
+
+    /// The first entry which is greater than the key.
+    pub fn upper_bound_by<F>(&self, mut f: F) -> Option<&'e mut TreeEnt<Type>>
+        where F: FnMut(&Type) -> Ordering
+    {
+        let mut ret = core::ptr::null();
+        let mut cur = self.root;
+        while let Some(c) = Self::_ref_ent_or_none(cur) {
+            if f(&c.elem) == Ordering::Greater {
+                ret = cur;
+                cur = c.left;
+            } else {
+                cur = c.right;
+            }
+        }
+        Self::_ref_ent_or_none(ret)
+    }
+
+    /// The last entry which is not greater than the key.
+    pub fn floor_by<F>(&self, f: F) -> Option<&'e mut TreeEnt<Type>>
+        where F: FnMut(&Type) -> Ordering
+    {
+        match self.upper_bound_by(f) {
+            Some(e) => self.get_prev(e),
+            None => self.get_last(),
+        }
+    }
+
+    pub fn iter(&mut self) -> TreeIter<'e, Type, Ref> {
+        TreeIter::<Type, Ref> {
+            next: Self::_leftmost(self.root),
+            _phantom1: PhantomData,
+            _phantom2: PhantomData,
+        }
+    }
+}
+
+// Iterator
+
+/// In-order iterator.
+pub struct TreeIter<'e, Type, Ref>
+    where Ref: Refer<TreeEnt<Type>> {
+    next: *const Link,
+
+    _phantom1: PhantomData<&'e Type>,
+    _phantom2: PhantomData<Ref>,
+}
+
+impl<'e, Type, Ref> Iterator for TreeIter<'e, Type, Ref>
+    where
+        Ref: Refer<TreeEnt<Type>>,
+        Type: 'e {
+    type Item = &'e mut Type;
+
+    fn next(&mut self) -> Option<&'e mut Type> {
+        let ret_ent = Tree::<Type, Ref>::_ref_ent_or_none(self.next)?;
+        self.next = Tree::<Type, Ref>::_next(self.next);
+        Some(ret_ent.ref_elem_mut())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    #[cfg(not(feature = "nobox"))]
+    extern crate alloc;
+
+    #[cfg(not(feature = "nobox"))]
+    use alloc::boxed::Box;
+    use std::vec::Vec;
+
+    use super::*;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    struct Data {
+        data: isize,
+    }
+
+    /// Check links and balance, and returns the height.
+    fn check<Ref>(tree: &Tree<Data, Ref>) -> usize
+        where Ref: Refer<TreeEnt<Data>>
+    {
+        check_ent::<Ref>(tree.root, core::ptr::null())
+    }
+
+    fn check_ent<Ref>(ent: *const Link, parent: *const Link) -> usize
+        where Ref: Refer<TreeEnt<Data>>
+    {
+        let e = match Tree::<Data, Ref>::_ref_ent_or_none(ent) {
+            Some(e) => e,
+            None => return 0,
+        };
+        assert_eq!(e.parent, parent);
+        if let Some(l) = Tree::<Data, Ref>::_ref_ent_or_none(e.left) {
+            assert!(l.elem <= e.elem);
+        }
+        if let Some(r) = Tree::<Data, Ref>::_ref_ent_or_none(e.right) {
+            assert!(e.elem <= r.elem);
+        }
+        let lh = check_ent::<Ref>(e.left, ent);
+        let rh = check_ent::<Ref>(e.right, ent);
+        assert!(lh <= rh + 1 && rh <= lh + 1);
+        assert_eq!(e.height, 1 + core::cmp::max(lh, rh));
+        e.height
+    }
+
+    fn values<Ref>(tree: &mut Tree<Data, Ref>) -> Vec<isize>
+        where Ref: Refer<TreeEnt<Data>>
+    {
+        check(tree);
+        tree.iter().map(|x| x.data).collect()
+    }
+
+    fn test_tree<Ref, F>(tree: &mut Tree<Data, Ref>, mut new: F)
+    where
+        Ref: Refer<TreeEnt<Data>>,
+        F: FnMut(isize) -> Ref,
+    {
+        assert!(tree.is_empty());
+        assert!(tree.get_first().is_none());
+        assert!(tree.find_by(|x| x.data.cmp(&1)).is_none());
+        assert!(tree.lower_bound_by(|x| x.data.cmp(&1)).is_none());
+        assert!(tree.pop_first().is_none());
+
+        for &i in [50, 20, 80, 10, 30, 70, 90, 30, 60, 40].iter() {
+            tree.insert(new(i));
+        }
+        assert_eq!(values(tree), [10, 20, 30, 30, 40, 50, 60, 70, 80, 90]);
+        assert_eq!(tree.get_first().unwrap().data, 10);
+        assert_eq!(tree.get_last().unwrap().data, 90);
+
+        let key = |k: isize| move |x: &Data| x.data.cmp(&k);
+        assert_eq!(tree.find_by(key(60)).unwrap().data, 60);
+        assert!(tree.find_by(key(65)).is_none());
+        assert_eq!(tree.lower_bound_by(key(30)).unwrap().data, 30);
+        assert_eq!(tree.lower_bound_by(key(31)).unwrap().data, 40);
+        assert!(tree.lower_bound_by(key(91)).is_none());
+        assert_eq!(tree.upper_bound_by(key(30)).unwrap().data, 40);
+        assert_eq!(tree.upper_bound_by(key(0)).unwrap().data, 10);
+        assert!(tree.upper_bound_by(key(90)).is_none());
+        assert_eq!(tree.floor_by(key(35)).unwrap().data, 30);
+        assert_eq!(tree.floor_by(key(100)).unwrap().data, 90);
+        assert!(tree.floor_by(key(5)).is_none());
+
+        // neighbours and equal entries.
+        let e = tree.lower_bound_by(key(30)).unwrap();
+        let e2 = tree.get_next(e).unwrap();
+        assert_eq!(e2.data, 30);
+        assert_eq!(tree.get_next(e2).unwrap().data, 40);
+        assert_eq!(tree.get_prev(e).unwrap().data, 20);
+        assert!(tree.get_prev(tree.get_first().unwrap()).is_none());
+        assert!(tree.get_next(tree.get_last().unwrap()).is_none());
+
+        let x = tree.remove(e);
+        assert_eq!(x.data, 30);
+        assert_eq!(values(tree), [10, 20, 30, 40, 50, 60, 70, 80, 90]);
+        // an entry with two children.
+        let x = tree.remove(tree.find_by(key(50)).unwrap());
+        assert_eq!(x.data, 50);
+        assert_eq!(values(tree), [10, 20, 30, 40, 60, 70, 80, 90]);
+        tree.insert(x);
+        assert_eq!(values(tree), [10, 20, 30, 40, 50, 60, 70, 80, 90]);
+
+        let mut n = 0;
+        while let Some(x) = tree.pop_first() {
+            n += 1;
+            assert!(x.data >= 10);
+            check(tree);
+        }
+        assert_eq!(n, 9);
+        assert!(tree.is_empty());
+    }
+
+    #[test]
+    fn test_ref() {
+        let mut val: Vec<TreeEnt<Data>> = Vec::with_capacity(16);
+        let p = val.as_mut_ptr();
+        let mut n = 0;
+        let mut tree = Tree::<Data, &mut TreeEnt<Data>>::new();
+        test_tree(&mut tree, |data| unsafe {
+            let e = p.add(n);
+            n += 1;
+            e.write(TreeEnt::new(Data { data }));
+            &mut *e
+        });
+    }
+
+    #[cfg(not(feature = "nobox"))]
+    #[test]
+    fn test_box() {
+        let mut tree = Tree::<Data, Box<TreeEnt<Data>>>::new();
+        test_tree(&mut tree, |data| Box::new(TreeEnt::new(Data { data })));
+    }
+
+    #[test]
+    fn test_raw() {
+        let mut val: Vec<TreeEnt<Data>> =
+            (0..16).map(|_| TreeEnt::new(Data { data: 0 })).collect();
+        let mut iter = val.iter_mut();
+        let mut tree = Tree::<Data, RawRefer<TreeEnt<Data>>>::new();
+        test_tree(&mut tree, |data| {
+            let e = iter.next().unwrap();
+            e.data = data;
+            e.into()
+        });
+    }
+
+    /// Random inserts and removes against a sorted Vec.
+    #[test]
+    fn test_random() {
+        let mut val: Vec<TreeEnt<Data>> =
+            (0..256).map(|i| TreeEnt::new(Data { data: i })).collect();
+        let mut tree = Tree::<Data, RawRefer<TreeEnt<Data>>>::new();
+        let mut inside = [false; 256];
+        let mut model: Vec<isize> = Vec::new();
+        let mut r: u64 = 0x2545_f491_4f6c_dd1d;
+        for _ in 0..5000 {
+            r ^= r << 13;
+            r ^= r >> 7;
+            r ^= r << 17;
+            let i = (r % 256) as usize;
+            let data = i as isize / 2;  // make duplicates.
+            if inside[i] {
+                let x = tree.remove(&val[i]);
+                assert_eq!(x.data, data);
+                let pos = model.iter().position(|&x| x == data).unwrap();
+                model.remove(pos);
+            } else {
+                val[i].data = data;
+                tree.insert((&mut val[i]).into());
+                let pos = model.iter().position(|&x| x > data)
+                    .unwrap_or(model.len());
+                model.insert(pos, data);
+            }
+            inside[i] = !inside[i];
+            // height is O(log n).
+            assert!(Tree::<Data, RawRefer<TreeEnt<Data>>>::_height(tree.root)
+                <= 12);
+        }
+        assert_eq!(values(&mut tree), model);
+        let mut prev = None;
+        while let Some(e) = tree.get_last() {
+            if let Some(p) = prev {
+                assert!(e.data <= p);
+            }
+            prev = Some(e.data);
+            tree.remove(e);
+        }
+    }
+}
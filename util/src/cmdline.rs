@@ -0,0 +1,203 @@
+// Uniqos  --  Unique Operating System
+// (c) 2019 KATO Takeshi
+// Released under the MIT license
+
+//! Kernel command line.
+//!
+//! Arguments are separated by spaces.  Each argument is `key=value` or a
+//! bare flag `key`.  Double quotes keep spaces: `init="/bin/sh -x"`.
+//! When a key is repeated, the last one wins in `get()`.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Arg<'a> {
+    pub key: &'a str,
+    /// None for a bare flag.
+    pub value: Option<&'a str>,
+}
+
+impl<'a> Arg<'a> {
+    /// Comma separated items of the value: `console=serial,vga`.
+    pub fn items(&self) -> impl Iterator<Item = &'a str> {
+        self.value.unwrap_or("").split(',').filter(|s| !s.is_empty())
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Cmdline<'a> {
+    s: &'a str,
+}
+
+impl<'a> Cmdline<'a> {
+    pub const fn new(s: &'a str) -> Self {
+        Cmdline { s }
+    }
+
+    pub fn as_str(&self) -> &'a str {
+        self.s
+    }
+
+    pub fn iter(&self) -> Iter<'a> {
+        Iter { rest: self.s }
+    }
+
+    /// The last argument of `key`.
+    pub fn get(&self, key: &str) -> Option<Arg<'a>> {
+        self.iter().filter(|arg| arg.key == key).last()
+    }
+
+    /// The last value of `key`.  None for a bare flag.
+    pub fn value(&self, key: &str) -> Option<&'a str> {
+        self.get(key)?.value
+    }
+
+    pub fn has(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// All arguments of `key` in order.
+    pub fn get_all<'b>(&self, key: &'b str)
+        -> impl Iterator<Item = Arg<'a>> + 'b
+        where 'a: 'b
+    {
+        self.iter().filter(move |arg| arg.key == key)
+    }
+}
+
+impl<'a> IntoIterator for Cmdline<'a> {
+    type Item = Arg<'a>;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+pub struct Iter<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = Arg<'a>;
+
+    fn next(&mut self) -> Option<Arg<'a>> {
+        let s = self.rest.trim_start();
+        if s.is_empty() {
+            self.rest = s;
+            return None;
+        }
+
+        // an unterminated quote continues to the end.
+        let mut quoted = false;
+        let mut end = s.len();
+        for (i, c) in s.char_indices() {
+            if c == '"' {
+                quoted = !quoted;
+            } else if c.is_whitespace() && !quoted {
+                end = i;
+                break;
+            }
+        }
+        let (token, rest) = s.split_at(end);
+        self.rest = rest;
+
+        Some(match token.find('=') {
+            Some(i) => Arg {
+                key: unquote(&token[..i]),
+                value: Some(unquote(&token[i + 1..])),
+            },
+            None => Arg { key: unquote(token), value: None },
+        })
+    }
+}
+
+/// Strip a quote on each side.  `"key=value"` is split to `"key` and
+/// `value"`, so each side is stripped separately.
+fn unquote(s: &str) -> &str {
+    let s = s.strip_prefix('"').unwrap_or(s);
+    s.strip_suffix('"').unwrap_or(s)
+}
+
+/// Parse a size such as `512M`, `0x1000` and `4G`.  None if invalid or
+/// overflowed.
+pub fn parse_size(s: &str) -> Option<u64> {
+    let (num, shift) = match s.as_bytes().last()? {
+        b'k' | b'K' => (&s[..s.len() - 1], 10),
+        b'm' | b'M' => (&s[..s.len() - 1], 20),
+        b'g' | b'G' => (&s[..s.len() - 1], 30),
+        _ => (s, 0),
+    };
+    let n = match num.strip_prefix("0x").or(num.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+        None => num.parse::<u64>().ok()?,
+    };
+    n.checked_mul(1 << shift)
+}
+
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+    use std::vec::Vec;
+
+    use super::*;
+
+    fn arg<'a>(key: &'a str, value: Option<&'a str>) -> Arg<'a> {
+        Arg { key, value }
+    }
+
+    #[test]
+    fn test_args() {
+        let cmdline = Cmdline::new("  root=cd0 debug\tconsole=serial,vga  ");
+        let args: Vec<_> = cmdline.iter().collect();
+        assert_eq!(args, [
+            arg("root", Some("cd0")),
+            arg("debug", None),
+            arg("console", Some("serial,vga")),
+        ]);
+
+        assert_eq!(cmdline.value("root"), Some("cd0"));
+        assert!(cmdline.has("debug"));
+        assert_eq!(cmdline.value("debug"), None);
+        assert!(!cmdline.has("quiet"));
+        let items: Vec<_> = cmdline.get("console").unwrap().items().collect();
+        assert_eq!(items, ["serial", "vga"]);
+
+        assert_eq!(Cmdline::new("").iter().next(), None);
+        assert_eq!(Cmdline::new("key=").value("key"), Some(""));
+    }
+
+    #[test]
+    fn test_quote() {
+        let cmdline = Cmdline::new(
+            "init=\"/bin/sh -x\" \"msg=a b\" last=\"unterminated x");
+        let args: Vec<_> = cmdline.iter().collect();
+        assert_eq!(args, [
+            arg("init", Some("/bin/sh -x")),
+            arg("msg", Some("a b")),
+            arg("last", Some("unterminated x")),
+        ]);
+    }
+
+    #[test]
+    fn test_repeat() {
+        let cmdline = Cmdline::new("loglevel=3 quiet loglevel=7");
+        assert_eq!(cmdline.value("loglevel"), Some("7"));
+        let all: Vec<_> = cmdline.get_all("loglevel")
+            .map(|arg| arg.value.unwrap())
+            .collect();
+        assert_eq!(all, ["3", "7"]);
+    }
+
+    #[test]
+    fn test_size() {
+        assert_eq!(parse_size("4096"), Some(4096));
+        assert_eq!(parse_size("0x1000"), Some(0x1000));
+        assert_eq!(parse_size("64k"), Some(64 << 10));
+        assert_eq!(parse_size("512M"), Some(512 << 20));
+        assert_eq!(parse_size("4G"), Some(4 << 30));
+        assert_eq!(parse_size(""), None);
+        assert_eq!(parse_size("M"), None);
+        assert_eq!(parse_size("12x"), None);
+        assert_eq!(parse_size("0xffffffffffffffffG"), None);
+    }
+}
@@ -1,5 +1,188 @@
+// Uniqos  --  Unique Operating System
+// (c) 2019 KATO Takeshi
+// Released under the MIT license
 
-pub enum Error {
+//! Error codes shared by the loader and the kernel.
+
+use core::fmt;
+
+
+/// Stable error number.  The values are passed across the syscall
+/// boundary, so never renumber them.
+#[repr(i32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Code {
     Fail = 1,
     NoImpl = 2,
+    NoMem = 3,
+    Inval = 4,
+    Busy = 5,
+    NotFound = 6,
+    Io = 7,
+    Unsupported = 8,
+    Exist = 9,
+    Range = 10,
+    NoSpace = 11,
+    BadAdr = 12,
+}
+
+impl Code {
+    /// Unknown numbers are Fail.
+    pub fn from_i32(n: i32) -> Self {
+        match n {
+            2 => Code::NoImpl,
+            3 => Code::NoMem,
+            4 => Code::Inval,
+            5 => Code::Busy,
+            6 => Code::NotFound,
+            7 => Code::Io,
+            8 => Code::Unsupported,
+            9 => Code::Exist,
+            10 => Code::Range,
+            11 => Code::NoSpace,
+            12 => Code::BadAdr,
+            _ => Code::Fail,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Code::Fail => "failed",
+            Code::NoImpl => "not implemented",
+            Code::NoMem => "out of memory",
+            Code::Inval => "invalid argument",
+            Code::Busy => "busy",
+            Code::NotFound => "not found",
+            Code::Io => "I/O error",
+            Code::Unsupported => "not supported",
+            Code::Exist => "already exists",
+            Code::Range => "out of range",
+            Code::NoSpace => "no space left",
+            Code::BadAdr => "bad address",
+        }
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    Fail,
+    NoImpl,
+    NoMem,
+    Inval,
+    Busy,
+    NotFound,
+    Io,
+    Unsupported,
+    Exist,
+    Range,
+    NoSpace,
+    BadAdr,
+
+    /// Failed to allocate `bytes`.
+    NoMemFor { bytes: usize },
+    /// `adr` is not managed or not allocated.
+    BadAdrAt { adr: usize },
+    /// `code` with where it happened.
+    Context(Code, &'static str),
+}
+
+impl Error {
+    pub fn code(&self) -> Code {
+        match *self {
+            Error::Fail => Code::Fail,
+            Error::NoImpl => Code::NoImpl,
+            Error::NoMem => Code::NoMem,
+            Error::Inval => Code::Inval,
+            Error::Busy => Code::Busy,
+            Error::NotFound => Code::NotFound,
+            Error::Io => Code::Io,
+            Error::Unsupported => Code::Unsupported,
+            Error::Exist => Code::Exist,
+            Error::Range => Code::Range,
+            Error::NoSpace => Code::NoSpace,
+            Error::BadAdr => Code::BadAdr,
+            Error::NoMemFor { .. } => Code::NoMem,
+            Error::BadAdrAt { .. } => Code::BadAdr,
+            Error::Context(code, _) => code,
+        }
+    }
+
+    /// Add a message of where it happened.  The detail is dropped.
+    pub fn context(self, msg: &'static str) -> Self {
+        Error::Context(self.code(), msg)
+    }
+}
+
+impl From<Code> for Error {
+    fn from(code: Code) -> Self {
+        match code {
+            Code::Fail => Error::Fail,
+            Code::NoImpl => Error::NoImpl,
+            Code::NoMem => Error::NoMem,
+            Code::Inval => Error::Inval,
+            Code::Busy => Error::Busy,
+            Code::NotFound => Error::NotFound,
+            Code::Io => Error::Io,
+            Code::Unsupported => Error::Unsupported,
+            Code::Exist => Error::Exist,
+            Code::Range => Error::Range,
+            Code::NoSpace => Error::NoSpace,
+            Code::BadAdr => Error::BadAdr,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Error::NoMemFor { bytes } =>
+                write!(f, "{}: {:#x} bytes", self.code(), bytes),
+            Error::BadAdrAt { adr } =>
+                write!(f, "{}: {:#x}", self.code(), adr),
+            Error::Context(code, msg) =>
+                write!(f, "{}: {}", msg, code),
+            _ => self.code().fmt(f),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use std::format;
+
+    use super::*;
+
+    #[test]
+    fn test_code() {
+        for n in 1..=12 {
+            let code = Code::from_i32(n);
+            assert_eq!(code as i32, n);
+            assert_eq!(Error::from(code).code(), code);
+        }
+        assert_eq!(Code::from_i32(0), Code::Fail);
+        assert_eq!(Code::from_i32(-1), Code::Fail);
+        assert_eq!(Error::NoMemFor { bytes: 8 }.code(), Code::NoMem);
+        assert_eq!(Error::BadAdrAt { adr: 8 }.code(), Code::BadAdr);
+        assert_eq!(Error::Busy.context("x").code(), Code::Busy);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(format!("{}", Error::Inval), "invalid argument");
+        assert_eq!(format!("{}", Error::NoMemFor { bytes: 0x100 }),
+                   "out of memory: 0x100 bytes");
+        assert_eq!(format!("{}", Error::BadAdrAt { adr: 0x1000 }),
+                   "bad address: 0x1000");
+        assert_eq!(format!("{}", Error::NoMem.context("range entries")),
+                   "range entries: out of memory");
+    }
 }
@@ -2,13 +2,217 @@
 // (c) 2019 KATO Takeshi
 // Released under the MIT license
 
-use core::mem;
+//! Fixed size buffer for core::fmt without heap.
+
+use core::fmt;
+use core::str;
+
+
+/// What to do when the buffer is full.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Overflow {
+    /// Keep the head and put the marker at the end.  Later writes are
+    /// ignored and succeed.
+    Truncate(&'static str),
+    /// Fail the write which does not fit.  The content written before it
+    /// is kept.
+    Error,
+}
 
 struct FormatBufferProp {
     buf_offset: usize,
+    policy: Overflow,
+    truncated: bool,
 }
 
-pub struct FormatBuffer {
+/// `N` is the capacity in bytes.  The content is always valid UTF-8.
+pub struct FormatBuffer<const N: usize = 128> {
     prop: FormatBufferProp,
-    buf: [u8; 128 - mem::size_of::<FormatBufferProp>()],
+    buf: [u8; N],
+}
+
+impl<const N: usize> Default for FormatBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> FormatBuffer<N> {
+    /// Truncate with "..." on overflow.
+    pub const fn new() -> Self {
+        Self::with_policy(Overflow::Truncate("..."))
+    }
+
+    pub const fn with_policy(policy: Overflow) -> Self {
+        Self {
+            prop: FormatBufferProp {
+                buf_offset: 0,
+                policy,
+                truncated: false,
+            },
+            buf: [0; N],
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.prop.buf_offset]
+    }
+
+    pub fn as_str(&self) -> &str {
+        // only whole str are copied, or cut at char boundaries.
+        unsafe { str::from_utf8_unchecked(self.as_bytes()) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.prop.buf_offset
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prop.buf_offset == 0
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// True if the content was cut by Overflow::Truncate.
+    pub fn is_truncated(&self) -> bool {
+        self.prop.truncated
+    }
+
+    pub fn clear(&mut self) {
+        self.prop.buf_offset = 0;
+        self.prop.truncated = false;
+    }
+
+    fn _push(&mut self, s: &[u8]) {
+        let off = self.prop.buf_offset;
+        self.buf[off..off + s.len()].copy_from_slice(s);
+        self.prop.buf_offset += s.len();
+    }
+
+    /// Cut the content to `len` bytes or less at a char boundary.
+    fn _cut(&mut self, len: usize) {
+        let mut len = len;
+        while len < self.prop.buf_offset && (self.buf[len] & 0xc0) == 0x80 {
+            len -= 1;
+        }
+        if len < self.prop.buf_offset {
+            self.prop.buf_offset = len;
+        }
+    }
+
+    fn _truncate(&mut self, s: &str, marker: &str) {
+        // fill the buffer, then make room for the marker.
+        let room = N - self.prop.buf_offset;
+        let mut head = room;
+        while !s.is_char_boundary(head) {
+            head -= 1;
+        }
+        self._push(&s.as_bytes()[..head]);
+
+        let mut marker_len = marker.len().min(N);
+        while !marker.is_char_boundary(marker_len) {
+            marker_len -= 1;
+        }
+        self._cut(N - marker_len);
+        self._push(&marker.as_bytes()[..marker_len]);
+        self.prop.truncated = true;
+    }
+}
+
+impl<const N: usize> fmt::Write for FormatBuffer<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.prop.truncated {
+            return Ok(());
+        }
+        if s.len() <= N - self.prop.buf_offset {
+            self._push(s.as_bytes());
+            return Ok(());
+        }
+        match self.prop.policy {
+            Overflow::Truncate(marker) => {
+                self._truncate(s, marker);
+                Ok(())
+            },
+            Overflow::Error => Err(fmt::Error),
+        }
+    }
+}
+
+impl<const N: usize> fmt::Display for FormatBuffer<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl<const N: usize> fmt::Debug for FormatBuffer<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use core::fmt::Write;
+
+    use super::*;
+
+    #[test]
+    fn test_write() {
+        let mut buf = FormatBuffer::<16>::new();
+        assert!(buf.is_empty());
+        assert_eq!(buf.capacity(), 16);
+        write!(buf, "{}-{:#x}", 12, 0xab).unwrap();
+        assert_eq!(buf.as_str(), "12-0xab");
+        assert_eq!(buf.as_bytes(), b"12-0xab");
+        assert_eq!(buf.len(), 7);
+        write!(buf, "{:9}", "x").unwrap();
+        // exactly full.
+        assert_eq!(buf.as_str(), "12-0xabx        ");
+        assert!(!buf.is_truncated());
+        buf.clear();
+        assert_eq!(buf.as_str(), "");
+
+        let buf = FormatBuffer::<128>::new();
+        assert_eq!(buf.capacity(), 128);
+    }
+
+    #[test]
+    fn test_truncate() {
+        let mut buf = FormatBuffer::<10>::new();
+        write!(buf, "abcdefgh").unwrap();
+        write!(buf, "ijk").unwrap();
+        assert_eq!(buf.as_str(), "abcdefg...");
+        assert!(buf.is_truncated());
+        // ignored after truncated.
+        write!(buf, "z").unwrap();
+        assert_eq!(buf.as_str(), "abcdefg...");
+        buf.clear();
+        assert!(!buf.is_truncated());
+
+        // cut at char boundaries.
+        let mut buf = FormatBuffer::<10>::with_policy(Overflow::Truncate("~"));
+        write!(buf, "aあいうえ").unwrap();
+        assert_eq!(buf.as_str(), "aあい~");
+        assert_eq!(buf.len(), 8);
+
+        // the marker is longer than the buffer.
+        let mut buf = FormatBuffer::<2>::with_policy(Overflow::Truncate("..."));
+        write!(buf, "abc").unwrap();
+        assert_eq!(buf.as_str(), "..");
+    }
+
+    #[test]
+    fn test_error() {
+        let mut buf = FormatBuffer::<8>::with_policy(Overflow::Error);
+        write!(buf, "abcd").unwrap();
+        assert!(write!(buf, "efghi").is_err());
+        assert_eq!(buf.as_str(), "abcd");
+        write!(buf, "efgh").unwrap();
+        assert!(write!(buf, "i").is_err());
+        assert_eq!(buf.as_str(), "abcdefgh");
+        assert!(!buf.is_truncated());
+    }
 }
@@ -1,22 +1,343 @@
+// Uniqos  --  Unique Operating System
+// (c) 2019 KATO Takeshi
+// Released under the MIT license
+
+//! I/O object model for devices and files.
+//!
+//! A device embeds IoNode at the head of a #[repr(C)] struct and gives a
+//! static IoNodeInterface, so its operations can cast the IoNode back to
+//! the device.  Operations take &IoNode; the device protects its own state.
+
+use core::ops::Deref;
+use core::ptr::NonNull;
+use core::sync::atomic::{self, AtomicU64, AtomicUsize, Ordering};
 
 use super::error::Error;
 
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SeekFrom {
+    Start(u64),
+    Current(i64),
+    End(i64),
+}
+
+/// Operations of an IoNode.  Unset operations return Error::NoImpl:
+///
+/// static CONSOLE_IF: IoNodeInterface = IoNodeInterface {
+///     write: console_write,
+///     ..IoNodeInterface::new()
+/// };
 pub struct IoNodeInterface {
-    write: fn(&mut IoNode, offset: isize, data: &[u8]) -> Result<usize, Error>,
+    /// Read into `data` from `offset`.  Returns the bytes read.
+    pub read: fn(&IoNode, offset: u64, data: &mut [u8]) -> Result<usize, Error>,
+    /// Write `data` at `offset`.  Returns the bytes written.
+    pub write: fn(&IoNode, offset: u64, data: &[u8]) -> Result<usize, Error>,
+    /// Returns the new position.
+    pub seek: fn(&IoNode, pos: SeekFrom) -> Result<u64, Error>,
+    pub ioctl: fn(&IoNode, cmd: usize, arg: usize) -> Result<usize, Error>,
+    pub flush: fn(&IoNode) -> Result<(), Error>,
+    /// Called when the last IoNodeRef is dropped.
+    pub close: fn(&IoNode),
+}
+
+impl Default for IoNodeInterface {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl IoNodeInterface {
-    pub fn new() -> Self {
+    pub const fn new() -> Self {
         IoNodeInterface {
+            read: Self::def_read,
+            write: Self::def_write,
+            seek: Self::def_seek,
+            ioctl: Self::def_ioctl,
+            flush: Self::def_flush,
+            close: Self::def_close,
         }
     }
-    fn def_write(&mut self, offset: isize, data: &[u8]) ->
+    fn def_read(_: &IoNode, _offset: u64, _data: &mut [u8]) ->
+        Result<usize, Error> { Err(Error::NoImpl) }
+    fn def_write(_: &IoNode, _offset: u64, _data: &[u8]) ->
         Result<usize, Error> { Err(Error::NoImpl) }
+    fn def_seek(_: &IoNode, _pos: SeekFrom) ->
+        Result<u64, Error> { Err(Error::NoImpl) }
+    fn def_ioctl(_: &IoNode, _cmd: usize, _arg: usize) ->
+        Result<usize, Error> { Err(Error::NoImpl) }
+    fn def_flush(_: &IoNode) -> Result<(), Error> { Err(Error::NoImpl) }
+    fn def_close(_: &IoNode) {}
 }
 
-struct IoNode {
-    ionif: &IoNodeInterface,
+/// The position is owned by one user at a time: read(), write() and
+/// seek() of a node must not run concurrently.  Share a node with
+/// read_at() and write_at() instead.
+pub struct IoNode {
+    ionif: &'static IoNodeInterface,
+    refs: AtomicUsize,
+    pos: AtomicU64,
 }
 
 impl IoNode {
+    pub const fn new(ionif: &'static IoNodeInterface) -> Self {
+        IoNode {
+            ionif,
+            refs: AtomicUsize::new(0),
+            pos: AtomicU64::new(0),
+        }
+    }
+
+    pub fn position(&self) -> u64 {
+        self.pos.load(Ordering::Relaxed)
+    }
+
+    /// Read from the current position and advance it.
+    pub fn read(&self, data: &mut [u8]) -> Result<usize, Error> {
+        let pos = self.position();
+        let n = (self.ionif.read)(self, pos, data)?;
+        self._advance(pos, n);
+        Ok(n)
+    }
+
+    /// Write at the current position and advance it.
+    pub fn write(&self, data: &[u8]) -> Result<usize, Error> {
+        let pos = self.position();
+        let n = (self.ionif.write)(self, pos, data)?;
+        self._advance(pos, n);
+        Ok(n)
+    }
+
+    /// Move the position from `pos` by `n` bytes.  It must be still `pos`
+    /// since the position is not shared.
+    fn _advance(&self, pos: u64, n: usize) {
+        let r = self.pos.compare_exchange(
+            pos, pos + n as u64, Ordering::Relaxed, Ordering::Relaxed);
+        debug_assert!(r.is_ok(), "IoNode position is moved concurrently");
+    }
+
+    /// Read from `offset`.  The position is not changed.
+    pub fn read_at(&self, offset: u64, data: &mut [u8])
+        -> Result<usize, Error>
+    {
+        (self.ionif.read)(self, offset, data)
+    }
+
+    /// Write at `offset`.  The position is not changed.
+    pub fn write_at(&self, offset: u64, data: &[u8]) -> Result<usize, Error> {
+        (self.ionif.write)(self, offset, data)
+    }
+
+    pub fn seek(&self, pos: SeekFrom) -> Result<u64, Error> {
+        let new_pos = (self.ionif.seek)(self, pos)?;
+        self.pos.store(new_pos, Ordering::Relaxed);
+        Ok(new_pos)
+    }
+
+    pub fn ioctl(&self, cmd: usize, arg: usize) -> Result<usize, Error> {
+        (self.ionif.ioctl)(self, cmd, arg)
+    }
+
+    pub fn flush(&self) -> Result<(), Error> {
+        (self.ionif.flush)(self)
+    }
+
+    /// Helper for seek operations: the position `pos` points in a node of
+    /// `size` bytes.
+    pub fn seek_pos(&self, pos: SeekFrom, size: u64) -> Result<u64, Error> {
+        let (base, off) = match pos {
+            SeekFrom::Start(p) => return Ok(p),
+            SeekFrom::Current(off) => (self.position(), off),
+            SeekFrom::End(off) => (size, off),
+        };
+        let r = if off < 0 {
+            base.checked_sub(off.unsigned_abs())
+        } else {
+            base.checked_add(off as u64)
+        };
+        r.ok_or(Error::Inval)
+    }
+}
+
+/// Reference counted handle of IoNode.
+/// The close operation is called when the last handle is dropped.
+pub struct IoNodeRef {
+    node: NonNull<IoNode>,
+}
+
+impl IoNodeRef {
+    /// Take a reference of `node`.
+    ///
+    /// # Safety
+    ///
+    /// `node` must live until its close operation is called.
+    pub unsafe fn new(node: &IoNode) -> Self {
+        node.refs.fetch_add(1, Ordering::Relaxed);
+        IoNodeRef {
+            node: NonNull::from(node),
+        }
+    }
+
+    pub fn ref_count(&self) -> usize {
+        self.refs.load(Ordering::Relaxed)
+    }
+}
+
+impl Clone for IoNodeRef {
+    fn clone(&self) -> Self {
+        unsafe { IoNodeRef::new(self) }
+    }
+}
+
+impl Drop for IoNodeRef {
+    fn drop(&mut self) {
+        if self.refs.fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+        atomic::fence(Ordering::Acquire);
+        (self.ionif.close)(self);
+    }
+}
+
+impl Deref for IoNodeRef {
+    type Target = IoNode;
+
+    fn deref(&self) -> &IoNode {
+        unsafe { self.node.as_ref() }
+    }
+}
+
+unsafe impl Send for IoNodeRef {}
+unsafe impl Sync for IoNodeRef {}
+
+
+#[cfg(test)]
+mod test {
+    use core::cell::UnsafeCell;
+    use core::sync::atomic::AtomicUsize;
+
+    use super::*;
+
+    /// Memory file of 16 bytes.
+    #[repr(C)]
+    struct MemFile {
+        node: IoNode,
+        buf: UnsafeCell<[u8; 16]>,
+        closed: AtomicUsize,
+    }
+
+    impl MemFile {
+        fn new() -> Self {
+            MemFile {
+                node: IoNode::new(&MEMFILE_IF),
+                buf: UnsafeCell::new([0; 16]),
+                closed: AtomicUsize::new(0),
+            }
+        }
+        fn from_node(node: &IoNode) -> &MemFile {
+            unsafe { &*(node as *const IoNode as *const MemFile) }
+        }
+        fn buf(node: &IoNode) -> *mut [u8; 16] {
+            Self::from_node(node).buf.get()
+        }
+    }
+
+    fn mem_read(node: &IoNode, offset: u64, data: &mut [u8])
+        -> Result<usize, Error>
+    {
+        let buf = unsafe { &mut *MemFile::buf(node) };
+        let start = (offset as usize).min(buf.len());
+        let n = data.len().min(buf.len() - start);
+        data[..n].copy_from_slice(&buf[start..start + n]);
+        Ok(n)
+    }
+
+    fn mem_write(node: &IoNode, offset: u64, data: &[u8])
+        -> Result<usize, Error>
+    {
+        let buf = unsafe { &mut *MemFile::buf(node) };
+        if offset as usize >= buf.len() {
+            return Err(Error::NoSpace);
+        }
+        let start = offset as usize;
+        let n = data.len().min(buf.len() - start);
+        buf[start..start + n].copy_from_slice(&data[..n]);
+        Ok(n)
+    }
+
+    fn mem_seek(node: &IoNode, pos: SeekFrom) -> Result<u64, Error> {
+        node.seek_pos(pos, 16)
+    }
+
+    fn mem_close(node: &IoNode) {
+        MemFile::from_node(node).closed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    static MEMFILE_IF: IoNodeInterface = IoNodeInterface {
+        read: mem_read,
+        write: mem_write,
+        seek: mem_seek,
+        close: mem_close,
+        ..IoNodeInterface::new()
+    };
+
+    static EMPTY_IF: IoNodeInterface = IoNodeInterface::new();
+
+    #[test]
+    fn test_default() {
+        let node = IoNode::new(&EMPTY_IF);
+        let mut buf = [0u8; 4];
+        assert_eq!(node.read(&mut buf), Err(Error::NoImpl));
+        assert_eq!(node.write(&buf), Err(Error::NoImpl));
+        assert_eq!(node.seek(SeekFrom::Start(1)), Err(Error::NoImpl));
+        assert_eq!(node.ioctl(1, 2), Err(Error::NoImpl));
+        assert_eq!(node.flush(), Err(Error::NoImpl));
+        assert_eq!(node.position(), 0);
+        let r = unsafe { IoNodeRef::new(&node) };
+        drop(r);
+    }
+
+    #[test]
+    fn test_rw() {
+        let f = MemFile::new();
+        let node = &f.node;
+        assert_eq!(node.write(b"hello, "), Ok(7));
+        assert_eq!(node.write(b"world"), Ok(5));
+        assert_eq!(node.position(), 12);
+        assert_eq!(node.write(b"123456"), Ok(4));
+        assert_eq!(node.write(b"7"), Err(Error::NoSpace));
+        assert_eq!(node.position(), 16);
+
+        let mut buf = [0u8; 8];
+        assert_eq!(node.seek(SeekFrom::Start(7)), Ok(7));
+        assert_eq!(node.read(&mut buf[..5]), Ok(5));
+        assert_eq!(&buf[..5], b"world");
+        assert_eq!(node.seek(SeekFrom::Current(-12)), Ok(0));
+        assert_eq!(node.read_at(12, &mut buf), Ok(4));
+        assert_eq!(&buf[..4], b"1234");
+        assert_eq!(node.position(), 0);
+        assert_eq!(node.seek(SeekFrom::End(-4)), Ok(12));
+        assert_eq!(node.seek(SeekFrom::Current(-13)), Err(Error::Inval));
+        assert_eq!(node.position(), 12);
+        assert_eq!(node.write_at(0, b"H"), Ok(1));
+        assert_eq!(node.read_at(0, &mut buf[..5]), Ok(5));
+        assert_eq!(&buf[..5], b"Hello");
+        assert_eq!(node.flush(), Err(Error::NoImpl));
+    }
+
+    #[test]
+    fn test_ref() {
+        let f = MemFile::new();
+        let r1 = unsafe { IoNodeRef::new(&f.node) };
+        let r2 = r1.clone();
+        assert_eq!(r1.ref_count(), 2);
+        assert_eq!(r2.write(b"abc"), Ok(3));
+        // handles share the node.
+        assert_eq!(r1.position(), 3);
+        drop(r1);
+        assert_eq!(f.closed.load(Ordering::Relaxed), 0);
+        assert_eq!(r2.ref_count(), 1);
+        drop(r2);
+        assert_eq!(f.closed.load(Ordering::Relaxed), 1);
+    }
 }
@@ -4,15 +4,19 @@
 
 #![no_std]
 
-// required by list
-#![feature(trait_alias)]
-
 pub mod boxed;
 //pub mod chain;
 pub mod cheap_alloc;
+mod cheap_alloc_test;
 pub mod cheap_list;
+pub mod cheap_tree;
+pub mod cmdline;
 pub mod error;
 pub mod format_buffer;
-//pub mod list;
+pub mod io_node;
+pub mod list;
+pub mod once;
 pub mod ops;
+pub mod spinlock;
+pub mod zeroable;
 
@@ -2,79 +2,20 @@
 // (c) 2019 KATO Takeshi
 // Released under the MIT license
 //
-/// Original linked list.
+//! Original linked list.
+//!
+//! Unlike cheap_list, the nodes are fields of the element.  An element can
+//! be linked to several lists at once through separate node fields, and the
+//! list selects its node field by a ListAdapter.
+//! When an element is in several lists, at most one of them may own it
+//! (e.g. by Box); the others should use RawRefer or &mut.
 
-#[cfg(not(feature = "nobox"))]
-extern crate alloc;
-
-#[cfg(not(feature = "nobox"))]
-use alloc::boxed::Box;
-
-use core::convert::Into;
 use core::fmt;
 use core::marker::PhantomData;
-use core::ops::DerefMut;
-use core::ops::Deref;
-use core::ptr::NonNull;
-
-
-pub unsafe trait Refer<T: ?Sized>: DerefMut<Target = T> + Sized {
-    fn into_ptr(self) -> *const T;
-    unsafe fn from_ptr(ptr: *const T) -> Self;
-}
-
-unsafe impl<'a, T: ?Sized> Refer<T> for &'a mut T {
-    fn into_ptr(self) -> *const T {
-        self
-    }
-    unsafe fn from_ptr(ptr: *const T) -> Self {
-        &mut *(ptr as *mut T)
-    }
-}
-
-#[cfg(not(feature = "nobox"))]
-unsafe impl<T: ?Sized> Refer<T> for Box<T> {
-    fn into_ptr(self) -> *const T {
-        Box::into_raw(self)
-    }
-    unsafe fn from_ptr(ptr: *const T) -> Self {
-        Box::from_raw(ptr as *mut T)
-    }
-}
-
-pub struct RawRefer<T: ?Sized> {
-    ptr: NonNull<T>,
-}
-impl<T: ?Sized> RawRefer<T> {
-    pub fn as_mut<'t>(&mut self) -> &'t mut T {
-        unsafe { &mut *self.ptr.as_ptr() }
-    }
-}
-
-impl<T: ?Sized> Deref for RawRefer<T> {
-    type Target = T;
-    fn deref(&self) -> &Self::Target {
-        unsafe { self.ptr.as_ref() }
-    }
-}
-impl<T: ?Sized> DerefMut for RawRefer<T> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        unsafe { self.ptr.as_mut() }
-    }
-}
-unsafe impl<T: ?Sized> Refer<T> for RawRefer<T> {
-    fn into_ptr(self) -> *const T {
-        self.ptr.as_ptr()
-    }
-    unsafe fn from_ptr(ptr: *const T) -> Self {
-        Self {
-            ptr: NonNull::<T>::new_unchecked(ptr as *mut T)
-        }
-    }
-}
 
+pub use super::cheap_list::{Link, RawRefer, Refer};
+use super::zeroable::Zeroable;
 
-pub struct Link;
 
 // Node trait
 pub trait NodeTrait {
@@ -97,13 +38,19 @@ impl ForwardNode {
     }
 }
 
+impl Default for ForwardNode {
+    fn default() -> Self {
+        ForwardNode::new()
+    }
+}
+
 impl Clone for ForwardNode {
     fn clone(&self) -> Self {
         ForwardNode::new()
     }
 }
 
-impl Copy for ForwardNode {}
+unsafe impl Zeroable for ForwardNode {}
 
 impl fmt::Debug for ForwardNode {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -124,8 +71,8 @@ impl NodeTrait for ForwardNode {
 }
 
 // Node
-/// Bidirectional.
 
+/// Bidirectional.
 pub struct Node {
     next: *const Link,
     prev: *const Link,
@@ -143,13 +90,19 @@ impl Node {
     }
 }
 
+impl Default for Node {
+    fn default() -> Self {
+        Node::new()
+    }
+}
+
 impl Clone for Node {
     fn clone(&self) -> Self {
         Node::new()
     }
 }
 
-impl Copy for Node {}
+unsafe impl Zeroable for Node {}
 
 impl fmt::Debug for Node {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -234,45 +187,86 @@ impl EndTrait for DualEnd {
     }
 }
 
+unsafe impl Zeroable for SingleEnd {}
+unsafe impl Zeroable for DualEnd {}
+
+// Adapter
+
+/// Selects the node field of Elem which a list uses.
+/// Implement one adapter for each list the element can be in.
 pub trait ListAdapter {
-    fn ref_node(&self) -> &Node;
+    type Elem;
+    type NodeType: NodeTrait;
+    fn ref_node(elem: &Self::Elem) -> &Self::NodeType;
+    fn mut_node(elem: &mut Self::Elem) -> &mut Self::NodeType;
+}
+
+/// Define a ListAdapter which selects `$field` of `$elem`.
+///
+/// list_adapter!(pub RunQueue, Thread, run_node, Node);
+#[macro_export]
+macro_rules! list_adapter {
+    ($vis:vis $adapter:ident, $elem:ty, $field:ident, $node:ty) => {
+        $vis struct $adapter;
+
+        impl $crate::list::ListAdapter for $adapter {
+            type Elem = $elem;
+            type NodeType = $node;
+            fn ref_node(elem: &$elem) -> &$node {
+                &elem.$field
+            }
+            fn mut_node(elem: &mut $elem) -> &mut $node {
+                &mut elem.$field
+            }
+        }
+    };
 }
 
 // ListImpl
 
-pub struct ListImpl<Type, Ref, Nod, End, Adp> 
+pub struct ListImpl<Type, Ref, Nod, End, Adp>
     where
         Ref: Refer<Type>,
         Nod: NodeTrait,
         End: EndTrait,
-        Adp: ListAdapter<NodeType = Nod> {
+        Adp: ListAdapter<Elem = Type, NodeType = Nod> {
     end: End,
 
     _phantom1: PhantomData<Type>,
     _phantom2: PhantomData<Ref>,
     _phantom3: PhantomData<Nod>,
+    _phantom4: PhantomData<Adp>,
 }
-/*
-impl<Type, Ref, Nod, End, Adp> Clone for ListImpl<Type, Ref, Nod, End, Adp>
+
+/// A zeroed list is empty.
+unsafe impl<Type, Ref, Nod, End, Adp> Zeroable
+    for ListImpl<Type, Ref, Nod, End, Adp>
+    where
+        Ref: Refer<Type>,
+        Nod: NodeTrait,
+        End: EndTrait + Zeroable,
+        Adp: ListAdapter<Elem = Type, NodeType = Nod> {}
+
+impl<Type, Ref, Nod, End, Adp> Default for ListImpl<Type, Ref, Nod, End, Adp>
     where
         Ref: Refer<Type>,
         Nod: NodeTrait,
         End: EndTrait,
-        Adp: ListAdapter<NodeType = Nod> {
+        Adp: ListAdapter<Elem = Type, NodeType = Nod> {
 
-    fn clone(&self) -> Self {
-        new()
+    fn default() -> Self {
+        Self::new()
     }
 }
-*/
 
 /// implement for any ListImpl includes SingleForwardList.
-impl<Type, Ref, Nod, End, Adp> ListImpl<Type, Ref, Nod, End, Adp>
+impl<'e, Type, Ref, Nod, End, Adp> ListImpl<Type, Ref, Nod, End, Adp>
     where
         Ref: Refer<Type>,
         Nod: NodeTrait,
         End: EndTrait,
-        Adp: ListAdapter<NodeType = Nod> {
+        Adp: ListAdapter<Elem = Type, NodeType = Nod>,
+        Type: 'e {
 
     pub fn new() -> Self {
         Self {
@@ -280,6 +274,7 @@ impl<Type, Ref, Nod, End, Adp> ListImpl<Type, Ref, Nod, End, Adp>
             _phantom1: PhantomData,
             _phantom2: PhantomData,
             _phantom3: PhantomData,
+            _phantom4: PhantomData,
         }
     }
 
@@ -299,6 +294,20 @@ impl<Type, Ref, Nod, End, Adp> ListImpl<Type, Ref, Nod, End, Adp>
         unsafe { (ent as *mut Type).as_mut() }
     }
 
+    fn _mut_node<'t>(ent: *const Link) -> &'t mut Nod
+        where Type: 't
+    {
+        Adp::mut_node(Self::_mut_ent(ent))
+    }
+
+    fn _link(ent: &Type) -> *const Link {
+        ent as *const Type as *const Link
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.end.get_head().is_null()
+    }
+
     pub fn ref_front(&self) -> Option<&Type> {
         Self::_ref_ent_or_none(self.end.get_head())
     }
@@ -311,75 +320,77 @@ impl<Type, Ref, Nod, End, Adp> ListImpl<Type, Ref, Nod, End, Adp>
         Self::_ref_ent_or_none(Adp::ref_node(base).get_next())
     }
 
+    pub fn get_front(&self) -> Option<&'e mut Type> {
+        Self::_mut_ent_or_none(self.end.get_head())
+    }
+
+    pub fn get_next(&self, base: &Type) -> Option<&'e mut Type> {
+        Self::_mut_ent_or_none(Adp::ref_node(base).get_next())
+    }
+
     pub fn push_front(&mut self, mut ent: Ref) {
         let old_head = self.end.get_head();
-        Adp::ref_node(&ent).set_prev(core::ptr::null());
-        Adp::ref_node(&ent).set_next(old_head);
+        Adp::mut_node(&mut *ent).set_prev(core::ptr::null());
+        Adp::mut_node(&mut *ent).set_next(old_head);
         let new_head = ent.into_ptr() as *const Link;
 
         self.end.set_head(new_head);
-        match Self::_mut_ent_or_null(old_head) {
-            Some(old_head) => Adp::ref_node(old_head).set_prev(new_head),
-            None => self.end.set_tail(new_head),
+        if !old_head.is_null() {
+            Self::_mut_node(old_head).set_prev(new_head);
+        } else {
+            self.end.set_tail(new_head);
         }
     }
 
-/*
     pub fn pop_front(&mut self) -> Option<Ref> {
         let old_head = self.end.get_head();
         if !old_head.is_null() {
-            let new_head = Self::_mut_ent(old_head).get_next();
+            let new_head = Self::_mut_node(old_head).get_next();
             self.end.set_head(new_head);
             if !new_head.is_null() {
-                Self::_mut_ent(new_head).set_prev(core::ptr::null());
+                Self::_mut_node(new_head).set_prev(core::ptr::null());
             } else {
                 self.end.set_tail(core::ptr::null());
             }
-            Some(unsafe { Refer::from_ptr(old_head as *const Entry) })
+            Some(unsafe { Refer::from_ptr(old_head as *const Type) })
         } else {
             None
         }
     }
 
-    pub fn insert_after(&mut self, base: &mut Entry, mut new_ent: Ref) {
-        let prev = base;
-        let next = Self::_ref_ent_or_none(prev.get_next());
+    pub fn insert_after(&mut self, base: &mut Type, mut new_ent: Ref) {
+        let prev = Self::_link(base);
+        let next = Adp::ref_node(base).get_next();
 
-        (*new_ent).set_prev(prev as *const Entry as *const Link);
-        (*new_ent).set_next(prev.get_next());
+        Adp::mut_node(&mut *new_ent).set_prev(prev);
+        Adp::mut_node(&mut *new_ent).set_next(next);
         let new_ent = new_ent.into_ptr() as *const Link;
-        prev.set_next(new_ent);
-        match next {
-            Some(n) => n.set_prev(new_ent),
-            None => self.end.set_tail(new_ent),
+        Adp::mut_node(base).set_next(new_ent);
+        if !next.is_null() {
+            Self::_mut_node(next).set_prev(new_ent);
+        } else {
+            self.end.set_tail(new_ent);
         }
     }
 
-    pub fn remove_next(&mut self, base: &mut Entry) -> Option<Ref>
-    {
-        match Self::_ref_ent_or_none(base.get_next()) {
-            Some(next1) => {
-                let next2 = next1.get_next();
-                base.set_next(next2);
-                let base_ptr = base as *const Entry as *const Link;
-                match Self::_ref_ent_or_none(next2) {
-                    Some(next2) => {
-                        next2.set_prev(base_ptr);
-                    }, 
-                    None => {
-                        self.end.set_tail(base_ptr)
-                    },
-                };
-                Some(unsafe { Refer::from_ptr(next1) })
-            },
-            None => { None },
+    pub fn remove_next(&mut self, base: &mut Type) -> Option<Ref> {
+        let next1 = Adp::ref_node(base).get_next();
+        if next1.is_null() {
+            return None;
+        }
+        let next2 = Self::_mut_node(next1).get_next();
+        let base_ptr = Self::_link(base);
+        Adp::mut_node(base).set_next(next2);
+        if !next2.is_null() {
+            Self::_mut_node(next2).set_prev(base_ptr);
+        } else {
+            self.end.set_tail(base_ptr);
         }
+        Some(unsafe { Refer::from_ptr(next1 as *const Type) })
     }
-*/
 
-/*
-    pub fn iter(&mut self) -> ListIter<'e, Type, Ref, End, Entry> {
-        ListIter::<Type, Ref, End, Entry> {
+    pub fn iter(&mut self) -> ListIter<'e, Type, Ref, Nod, End, Adp> {
+        ListIter::<Type, Ref, Nod, End, Adp> {
             next: self.end.get_head(),
             next_back: self.end.get_tail_or_null(),
 
@@ -387,105 +398,107 @@ impl<Type, Ref, Nod, End, Adp> ListImpl<Type, Ref, Nod, End, Adp>
             _phantom2: PhantomData,
             _phantom3: PhantomData,
             _phantom4: PhantomData,
+            _phantom5: PhantomData,
         }
     }
-*/
 }
 
-/*
 /// implement for ForwardList or List.
-impl<'e, Type, Ref, Entry> ListImpl<Type, Ref, DualEnd, Entry>
+impl<'e, Type, Ref, Nod, Adp> ListImpl<Type, Ref, Nod, DualEnd, Adp>
     where
-        Ref: Refer<Entry>,
-        Entry: EntBase<Type> + 'e {
+        Ref: Refer<Type>,
+        Nod: NodeTrait,
+        Adp: ListAdapter<Elem = Type, NodeType = Nod>,
+        Type: 'e {
 
-    pub fn get_back(&self) -> Option<&'e mut Entry> {
-        let ret_raw = self.end.get_tail_or_null() as *mut Entry;
-        unsafe { ret_raw.as_mut() }
+    pub fn get_back(&self) -> Option<&'e mut Type> {
+        Self::_mut_ent_or_none(self.end.get_tail_or_null())
     }
 
     pub fn push_back(&mut self, mut ent: Ref) {
         let old_tail = self.end.get_tail_or_null();
-        (*ent).set_prev(old_tail);
-        (*ent).set_next(core::ptr::null());
+        Adp::mut_node(&mut *ent).set_prev(old_tail);
+        Adp::mut_node(&mut *ent).set_next(core::ptr::null());
         let new_tail = ent.into_ptr() as *const Link;
 
         self.end.set_tail(new_tail);
         if !old_tail.is_null() {
-            Self::_mut_ent(old_tail).set_next(new_tail);
+            Self::_mut_node(old_tail).set_next(new_tail);
         } else {
             self.end.set_head(new_tail);
         }
     }
 }
-*/
 
-/*
 /// implement for SingleList or List.
-impl<'e, Type, Ref, End> ListImpl<Type, Ref, End, Ent<Type>>
+impl<'e, Type, Ref, End, Adp> ListImpl<Type, Ref, Node, End, Adp>
     where
-        Ref: Refer<Ent<Type>>,
-        End: EndBase {
+        Ref: Refer<Type>,
+        End: EndTrait,
+        Adp: ListAdapter<Elem = Type, NodeType = Node>,
+        Type: 'e {
 
-    pub fn get_prev(base: &Ent<Type>) -> Option<&'e mut Ent<Type>> {
-        let ret_raw = base.get_prev() as *mut Ent<Type>;
-        unsafe { ret_raw.as_mut() }
+    pub fn get_prev(base: &Type) -> Option<&'e mut Type> {
+        Self::_mut_ent_or_none(Adp::ref_node(base).get_prev())
     }
 
-    pub fn insert_before(&mut self, base: &mut Ent<Type>, mut new_ent: Ref) {
-        let prev = Self::_ref_ent_or_none(base.get_prev());
-        let next = base;
+    pub fn insert_before(&mut self, base: &mut Type, mut new_ent: Ref) {
+        let prev = Adp::ref_node(base).get_prev();
+        let next = Self::_link(base);
 
-        (*new_ent).set_prev(next.get_prev());
-        (*new_ent).set_next(next as *const Ent<Type> as *const Link);
+        Adp::mut_node(&mut *new_ent).set_prev(prev);
+        Adp::mut_node(&mut *new_ent).set_next(next);
         let new_ent = new_ent.into_ptr() as *const Link;
-        next.set_prev(new_ent);
-        match prev {
-            Some(p) => p.set_next(new_ent),
-            None => self.end.set_head(new_ent),
+        Adp::mut_node(base).set_prev(new_ent);
+        if !prev.is_null() {
+            Self::_mut_node(prev).set_next(new_ent);
+        } else {
+            self.end.set_head(new_ent);
         }
     }
 
-    pub fn remove(&mut self, target: &Ent<Type>) -> Ref {
-        let prev = target.get_prev();
-        let next = target.get_next();
+    pub fn remove(&mut self, target: &Type) -> Ref {
+        let prev = Adp::ref_node(target).get_prev();
+        let next = Adp::ref_node(target).get_next();
 
-        match Self::_ref_ent_or_none(prev) {
-            Some(x) => x.set_next(next),
-            None => self.end.set_head(next),
-        };
+        if !prev.is_null() {
+            Self::_mut_node(prev).set_next(next);
+        } else {
+            self.end.set_head(next);
+        }
 
-        match Self::_ref_ent_or_none(next) {
-            Some(x) => x.set_prev(prev),
-            None => self.end.set_tail(prev),
+        if !next.is_null() {
+            Self::_mut_node(next).set_prev(prev);
+        } else {
+            self.end.set_tail(prev);
         }
 
         unsafe { Refer::from_ptr(target) }
     }
 }
-*/
 
-/*
 /// implement for List.
-impl<'e, Type, Ref> ListImpl<Type, Ref, DualEnd, Ent<Type>>
-    where Ref: Refer<Ent<Type>> {
+impl<Type, Ref, Adp> ListImpl<Type, Ref, Node, DualEnd, Adp>
+    where
+        Ref: Refer<Type>,
+        Adp: ListAdapter<Elem = Type, NodeType = Node> {
+
     pub fn pop_back(&mut self) -> Option<Ref> {
         let old_tail = self.end.get_tail_or_null();
         if !old_tail.is_null() {
-            let new_tail = Self::_mut_ent(old_tail).get_prev();
+            let new_tail = Self::_mut_node(old_tail).get_prev();
             self.end.set_tail(new_tail);
             if !new_tail.is_null() {
-                Self::_mut_ent(new_tail).set_next(core::ptr::null());
+                Self::_mut_node(new_tail).set_next(core::ptr::null());
             } else {
                 self.end.set_head(core::ptr::null());
             }
-            Some(unsafe { Refer::from_ptr(old_tail as *const Ent<Type>) })
+            Some(unsafe { Refer::from_ptr(old_tail as *const Type) })
         } else {
             None
         }
     }
 }
-*/
 
 pub type List<Type, Ref, Adapter> =
     ListImpl<Type, Ref, Node, DualEnd, Adapter>;
@@ -495,28 +508,33 @@ pub type ForwardList<Type, Ref, Adapter> =
     ListImpl<Type, Ref, ForwardNode, DualEnd, Adapter>;
 pub type SingleForwardList<Type, Ref, Adapter> =
     ListImpl<Type, Ref, ForwardNode, SingleEnd, Adapter>;
-/*
+
 // Iterator
 
-pub struct ListIter<'e, Type, Ref, End, Entry> 
+pub struct ListIter<'e, Type, Ref, Nod, End, Adp>
     where
-        Ref: Refer<Entry>,
-        End: EndBase,
-        Entry: EntBase<Type> {
+        Ref: Refer<Type>,
+        Nod: NodeTrait,
+        End: EndTrait,
+        Adp: ListAdapter<Elem = Type, NodeType = Nod> {
     next: *const Link,
     next_back: *const Link,
 
     _phantom1: PhantomData<&'e Type>,
     _phantom2: PhantomData<Ref>,
-    _phantom3: PhantomData<End>,
-    _phantom4: PhantomData<Entry>,
+    _phantom3: PhantomData<Nod>,
+    _phantom4: PhantomData<End>,
+    _phantom5: PhantomData<Adp>,
 }
 
-impl<'e, Type, Ref, End, Entry> Iterator for ListIter<'e, Type, Ref, End, Entry>
+impl<'e, Type, Ref, Nod, End, Adp> Iterator
+    for ListIter<'e, Type, Ref, Nod, End, Adp>
     where
-        Ref: Refer<Entry>,
-        End: EndBase,
-        Entry: EntBase<Type> + 'e {
+        Ref: Refer<Type>,
+        Nod: NodeTrait,
+        End: EndTrait,
+        Adp: ListAdapter<Elem = Type, NodeType = Nod>,
+        Type: 'e {
     type Item = &'e mut Type;
 
     fn next(&mut self) -> Option<&'e mut Type> {
@@ -524,623 +542,301 @@ impl<'e, Type, Ref, End, Entry> Iterator for ListIter<'e, Type, Ref, End, Entry>
             None
         } else {
             let ret_ent =
-                ListImpl::<Type, Ref, End, Entry>::_mut_ent(self.next);
+                ListImpl::<Type, Ref, Nod, End, Adp>::_mut_ent(self.next);
             if self.next == self.next_back {
                 self.next = core::ptr::null();
                 self.next_back = core::ptr::null();
             } else {
-                self.next = ret_ent.get_next();
+                self.next = Adp::ref_node(ret_ent).get_next();
             }
-            Some(ret_ent.ref_elem_mut())
+            Some(ret_ent)
         }
     }
 }
 
-impl<'e, Type, Ref> DoubleEndedIterator
-    for ListIter<'e, Type, Ref, DualEnd, Ent<Type>>
+impl<'e, Type, Ref, Adp> DoubleEndedIterator
+    for ListIter<'e, Type, Ref, Node, DualEnd, Adp>
     where
-        Ref: Refer<Ent<Type>>,
-        Ent<Type>: 'e {
+        Ref: Refer<Type>,
+        Adp: ListAdapter<Elem = Type, NodeType = Node>,
+        Type: 'e {
 
     fn next_back(&mut self) -> Option<&'e mut Type> {
         if self.next_back.is_null() {
             None
         } else {
-            let ret_ent = ListImpl::<Type, Ref, DualEnd, Ent<Type>>::_mut_ent(
+            let ret_ent = ListImpl::<Type, Ref, Node, DualEnd, Adp>::_mut_ent(
                 self.next_back);
             if self.next_back == self.next {
                 self.next = core::ptr::null();
                 self.next_back = core::ptr::null();
             } else {
-                self.next_back = ret_ent.get_prev();
+                self.next_back = Adp::ref_node(ret_ent).get_prev();
             }
-            Some(ret_ent.ref_elem_mut())
+            Some(ret_ent)
         }
     }
 }
-*/
 
 
 #[cfg(test)]
 mod test {
+    extern crate std;
+
+    #[cfg(not(feature = "nobox"))]
+    extern crate alloc;
+
+    #[cfg(not(feature = "nobox"))]
+    use alloc::boxed::Box;
+    use std::vec::Vec;
+
     use super::*;
 
-    #[derive(Clone, Copy, Debug, PartialEq)]
+    #[derive(Debug)]
     struct Data {
-        listnode: Node,
+        node: Node,
+        fnode: ForwardNode,
         data: isize,
     }
-    impl ListAdapter for Data {
-        fn ref_node(&self) -> &Node {
-            &self.Node
-        }
-    }
-
-    #[test]
-    fn test() {
-        let mut list = SingleForwardList::<Data, &mut Data, Data>::new();
-    }
 
-/*
-    fn test_pop_front<Ref>(
-        list: &mut List<Data, Ref>,
-        val1: Ref, val2: Ref, val3: Ref, val4: Ref)
-    where
-        Ref: Refer<Ent<Data>> + AsRef<Ent<Data>>,
-    {
-        let ref1 = unsafe { &*(val1.as_ref() as *const Ent<Data>) };
-        let ref2 = unsafe { &*(val2.as_ref() as *const Ent<Data>) };
-        let ref3 = unsafe { &*(val3.as_ref() as *const Ent<Data>) };
-        let ref4 = unsafe { &*(val4.as_ref() as *const Ent<Data>) };
-
-        assert!(list.get_front().is_none());
-        assert!(list.get_back().is_none());
-        assert!(list.pop_front().is_none());
-        list.push_front(val1);
-        // list: 1
-        assert_eq!(list.get_front().unwrap(), ref1);
-        assert_eq!(list.get_back().unwrap(), ref1);
-        list.push_front(val2);
-        // list: 2, 1
-        assert_eq!(list.get_front().unwrap(), ref2);
-        assert_eq!(list.get_back().unwrap(), ref1);
-        list.push_back(val3);
-        // list: 2, 1, 3
-        assert_eq!(list.get_front().unwrap(), ref2);
-        assert_eq!(list.get_back().unwrap(), ref3);
-        list.push_back(val4);
-        // list: 2, 1, 3, 4
-        assert_eq!(list.get_front().unwrap(), ref2);
-        assert_eq!(list.get_back().unwrap(), ref4);
-
-        let tmp = list.pop_front().unwrap();
-        // list: 1, 3, 4, tmp: 2
-        assert_eq!(tmp.as_ref(), ref2);
-        assert_eq!(list.get_front().unwrap(), ref1);
-        assert_eq!(list.get_back().unwrap(), ref4);
-        list.insert_after(list.get_front().unwrap(), tmp);
-        // list: 1, 2, 3, 4
-        assert_eq!(list.get_front().unwrap(), ref1);
-        assert_eq!(list.get_back().unwrap(), ref4);
-        for (x, y) in (1..).zip(list.iter()) {
-            assert_eq!(x, y.data);
-        }
-        let tmp = list.pop_front().unwrap();
-        // list: 2, 3, 4, tmp: 1
-        assert_eq!(tmp.as_ref(), ref1);
-        assert_eq!(list.get_front().unwrap(), ref2);
-        assert_eq!(list.get_back().unwrap(), ref4);
-        list.insert_after(list.get_back().unwrap(), tmp);
-        // list: 2, 3, 4, 1
-        assert_eq!(list.get_front().unwrap(), ref2);
-        assert_eq!(list.get_back().unwrap(), ref1);
-        let mut iter = list.iter().rev();
-        for x in [1, 4, 3, 2].iter() {
-            assert_eq!(iter.next(), Some(&mut Data { data: *x }));
+    impl Data {
+        fn new(data: isize) -> Self {
+            Self {
+                node: Node::new(),
+                fnode: ForwardNode::new(),
+                data,
+            }
         }
-        let tmp = list.pop_front().unwrap();
-        // list: 3, 4, 1, tmp: 2
-        assert_eq!(tmp.as_ref(), ref2);
-        assert_eq!(list.get_front().unwrap(), ref3);
-        assert_eq!(list.get_back().unwrap(), ref1);
-        list.insert_before(list.get_front().unwrap(), tmp);
-        // list: 2, 3, 4, 1
-        assert_eq!(list.get_front().unwrap(), ref2);
-        assert_eq!(list.get_back().unwrap(), ref1);
-        let tmp = list.pop_front().unwrap();
-        // list: 3, 4, 1, tmp: 2
-        assert_eq!(tmp.as_ref(), ref2);
-        assert_eq!(list.get_front().unwrap(), ref3);
-        assert_eq!(list.get_back().unwrap(), ref1);
-        list.insert_before(list.get_back().unwrap(), tmp);
-        // list: 3, 4, 2, 1
-        assert_eq!(list.get_front().unwrap(), ref3);
-        assert_eq!(list.get_back().unwrap(), ref1);
-        let tmp = list.pop_front().unwrap();
-        // list: 4, 2, 1, tmp: 3
-        assert_eq!(tmp.as_ref(), ref3);
-        assert_eq!(list.get_front().unwrap(), ref4);
-        assert_eq!(list.get_back().unwrap(), ref1);
-        let tmp = list.pop_front().unwrap();
-        // list: 2, 1, tmp: 4
-        assert_eq!(tmp.as_ref(), ref4);
-        assert_eq!(list.get_front().unwrap(), ref2);
-        assert_eq!(list.get_back().unwrap(), ref1);
-        let tmp = list.pop_front().unwrap();
-        // list: 1, tmp: 2
-        assert_eq!(tmp.as_ref(), ref2);
-        assert_eq!(list.get_front().unwrap(), ref1);
-        assert_eq!(list.get_back().unwrap(), ref1);
-        let tmp = list.pop_front().unwrap();
-        // list is empty, tmp: 1
-        assert_eq!(tmp.as_ref(), ref1);
-        assert!(list.get_front().is_none());
-        assert!(list.get_back().is_none());
-        assert!(list.pop_front().is_none());
     }
 
-    fn test_s_pop_front<Ref>(
-        list: &mut SingleList<Data, Ref>,
-        val1: Ref, val2: Ref)
+    list_adapter!(DataNode, Data, node, Node);
+    list_adapter!(DataFNode, Data, fnode, ForwardNode);
+
+    fn values<Ref, Nod, End, Adp>(list: &mut ListImpl<Data, Ref, Nod, End, Adp>)
+        -> Vec<isize>
     where
-        Ref: Refer<Ent<Data>> + AsRef<Ent<Data>>,
+        Ref: Refer<Data>,
+        Nod: NodeTrait,
+        End: EndTrait,
+        Adp: ListAdapter<Elem = Data, NodeType = Nod>,
     {
-        let ref1 = unsafe { &*(val1.as_ref() as *const Ent<Data>) };
-        let ref2 = unsafe { &*(val2.as_ref() as *const Ent<Data>) };
-
-        assert!(list.get_front().is_none());
-        assert!(list.pop_front().is_none());
-        list.push_front(val1);
-        // list: 1
-        assert_eq!(list.get_front().unwrap(), ref1);
-        list.push_front(val2);
-        // list: 2, 1
-        assert_eq!(list.get_front().unwrap(), ref2);
-        let tmp = list.pop_front().unwrap();
-        // list: 1, tmp: 2
-        assert_eq!(tmp.as_ref(), ref2);
-        assert_eq!(list.get_front().unwrap(), ref1);
-        list.insert_after(list.get_front().unwrap(), tmp);
-        // list: 1, 2
-        assert_eq!(list.get_front().unwrap(), ref1);
-        let mut iter = list.iter();
-        assert_eq!(iter.next(), Some(&mut Data { data: 1 }));
-        assert_eq!(iter.next(), Some(&mut Data { data: 2 }));
-        assert_eq!(iter.next(), None);
-        let tmp = list.pop_front().unwrap();
-        // list: 2, tmp: 1
-        assert_eq!(tmp.as_ref(), ref1);
-        assert_eq!(list.get_front().unwrap(), ref2);
-        list.insert_before(list.get_front().unwrap(), tmp);
-        // list: 1, 2
-        assert_eq!(list.get_front().unwrap(), ref1);
-        let tmp = list.pop_front().unwrap();
-        // list: 2, tmp: 1
-        assert_eq!(tmp.as_ref(), ref1);
-        assert_eq!(list.get_front().unwrap(), ref2);
-        let tmp = list.pop_front().unwrap();
-        // list is empty: tmp: 2
-        assert_eq!(tmp.as_ref(), ref2);
-        assert!(list.get_front().is_none());
-        assert!(list.pop_front().is_none());
+        list.iter().map(|x| x.data).collect()
     }
 
-    fn test_f_pop_front<Ref>(
-        list: &mut ForwardList<Data, Ref>,
+    fn test_pop_front<Ref, Nod, Adp>(
+        list: &mut ListImpl<Data, Ref, Nod, DualEnd, Adp>,
         val1: Ref, val2: Ref, val3: Ref, val4: Ref)
     where
-        Ref: Refer<ForwardEnt<Data>> + AsRef<ForwardEnt<Data>>,
+        Ref: Refer<Data>,
+        Nod: NodeTrait,
+        Adp: ListAdapter<Elem = Data, NodeType = Nod>,
     {
-        let ref1 = unsafe { &*(val1.as_ref() as *const ForwardEnt<Data>) };
-        let ref2 = unsafe { &*(val2.as_ref() as *const ForwardEnt<Data>) };
-        let ref3 = unsafe { &*(val3.as_ref() as *const ForwardEnt<Data>) };
-        let ref4 = unsafe { &*(val4.as_ref() as *const ForwardEnt<Data>) };
-
+        assert!(list.is_empty());
         assert!(list.get_front().is_none());
         assert!(list.get_back().is_none());
         assert!(list.pop_front().is_none());
         list.push_front(val1);
         // list: 1
-        assert_eq!(list.get_front().unwrap(), ref1);
-        assert_eq!(list.get_back().unwrap(), ref1);
+        assert_eq!(list.get_front().unwrap().data, 1);
+        assert_eq!(list.get_back().unwrap().data, 1);
         list.push_front(val2);
         // list: 2, 1
-        assert_eq!(list.get_front().unwrap(), ref2);
-        assert_eq!(list.get_back().unwrap(), ref1);
+        assert_eq!(list.ref_front().unwrap().data, 2);
+        assert_eq!(list.get_back().unwrap().data, 1);
         list.push_back(val3);
-        // list: 2, 1, 3
-        assert_eq!(list.get_front().unwrap(), ref2);
-        assert_eq!(list.get_back().unwrap(), ref3);
         list.push_back(val4);
         // list: 2, 1, 3, 4
-        assert_eq!(list.get_front().unwrap(), ref2);
-        assert_eq!(list.get_back().unwrap(), ref4);
-
-        let mut iter = list.iter();
-        assert_eq!(iter.next(), Some(&mut Data { data: 2 }));
-        assert_eq!(iter.next(), Some(&mut Data { data: 1 }));
-        assert_eq!(iter.next(), Some(&mut Data { data: 3 }));
-        assert_eq!(iter.next(), Some(&mut Data { data: 4 }));
-        assert_eq!(iter.next(), None);
+        assert_eq!(values(list), [2, 1, 3, 4]);
+        let front = list.ref_front().unwrap();
+        assert_eq!(list.ref_next(front).unwrap().data, 1);
 
         let tmp = list.pop_front().unwrap();
         // list: 1, 3, 4, tmp: 2
-        assert_eq!(tmp.as_ref(), ref2);
-        assert_eq!(list.get_front().unwrap(), ref1);
-        assert_eq!(list.get_back().unwrap(), ref4);
+        assert_eq!(tmp.data, 2);
         list.insert_after(list.get_front().unwrap(), tmp);
         // list: 1, 2, 3, 4
-        assert_eq!(list.get_front().unwrap(), ref1);
-        assert_eq!(list.get_back().unwrap(), ref4);
+        assert_eq!(values(list), [1, 2, 3, 4]);
         let tmp = list.pop_front().unwrap();
         // list: 2, 3, 4, tmp: 1
-        assert_eq!(tmp.as_ref(), ref1);
-        assert_eq!(list.get_front().unwrap(), ref2);
-        assert_eq!(list.get_back().unwrap(), ref4);
         list.insert_after(list.get_back().unwrap(), tmp);
         // list: 2, 3, 4, 1
-        assert_eq!(list.get_front().unwrap(), ref2);
-        assert_eq!(list.get_back().unwrap(), ref1);
-        let tmp = list.pop_front().unwrap();
-        // list: 3, 4, 1, tmp: 2
-        assert_eq!(tmp.as_ref(), ref2);
-        assert_eq!(list.get_front().unwrap(), ref3);
-        assert_eq!(list.get_back().unwrap(), ref1);
-        let mut iter = list.iter();
-        for x in [3, 4, 1].iter() {
-            assert_eq!(iter.next(), Some(&mut Data { data: *x }));
+        assert_eq!(values(list), [2, 3, 4, 1]);
+        assert_eq!(list.get_back().unwrap().data, 1);
+
+        let tmp = list.remove_next(list.get_front().unwrap()).unwrap();
+        // list: 2, 4, 1, tmp: 3
+        assert_eq!(tmp.data, 3);
+        list.push_back(tmp);
+        let base = list.get_next(list.get_front().unwrap()).unwrap();
+        let tmp = list.remove_next(base).unwrap();
+        // list: 2, 4, 3, tmp: 1
+        assert_eq!(tmp.data, 1);
+        assert_eq!(list.get_back().unwrap().data, 3);
+        assert!(list.remove_next(list.get_back().unwrap()).is_none());
+        list.push_front(tmp);
+        // list: 1, 2, 4, 3
+        assert_eq!(values(list), [1, 2, 4, 3]);
+
+        for x in [1, 2, 4, 3].iter() {
+            assert_eq!(list.pop_front().unwrap().data, *x);
         }
-        let tmp = list.pop_front().unwrap();
-        // list: 4, 1, tmp: 3
-        assert_eq!(tmp.as_ref(), ref3);
-        assert_eq!(list.get_front().unwrap(), ref4);
-        assert_eq!(list.get_back().unwrap(), ref1);
-        let tmp = list.pop_front().unwrap();
-        // list: 1, tmp: 4
-        assert_eq!(tmp.as_ref(), ref4);
-        assert_eq!(list.get_front().unwrap(), ref1);
-        assert_eq!(list.get_back().unwrap(), ref1);
-        let tmp = list.pop_front().unwrap();
-        // list is empty, tmp: 1
-        assert_eq!(tmp.as_ref(), ref1);
-        assert!(list.get_front().is_none());
+        assert!(list.is_empty());
         assert!(list.get_back().is_none());
         assert!(list.pop_front().is_none());
     }
 
-    fn test_sf_pop_front<Ref>(
-        list: &mut SingleForwardList<Data, Ref>,
+    fn test_s_pop_front<Ref, Nod, Adp>(
+        list: &mut ListImpl<Data, Ref, Nod, SingleEnd, Adp>,
         val1: Ref, val2: Ref)
     where
-        Ref: Refer<ForwardEnt<Data>> + AsRef<ForwardEnt<Data>>,
+        Ref: Refer<Data>,
+        Nod: NodeTrait,
+        Adp: ListAdapter<Elem = Data, NodeType = Nod>,
     {
-        let ref1 = unsafe { &*(val1.as_ref() as *const ForwardEnt<Data>) };
-        let ref2 = unsafe { &*(val2.as_ref() as *const ForwardEnt<Data>) };
-
         assert!(list.get_front().is_none());
         assert!(list.pop_front().is_none());
         list.push_front(val1);
-        // list: 1
-        assert_eq!(list.get_front().unwrap(), ref1);
         list.push_front(val2);
         // list: 2, 1
-        assert_eq!(list.get_front().unwrap(), ref2);
-
+        assert_eq!(values(list), [2, 1]);
         let tmp = list.pop_front().unwrap();
         // list: 1, tmp: 2
-        assert_eq!(tmp.as_ref(), ref2);
-        assert_eq!(list.get_front().unwrap(), ref1);
+        assert_eq!(tmp.data, 2);
         list.insert_after(list.get_front().unwrap(), tmp);
         // list: 1, 2
-        assert_eq!(list.get_front().unwrap(), ref1);
-        let tmp = list.pop_front().unwrap();
-        // list: 2, tmp: 1
-        assert_eq!(tmp.as_ref(), ref1);
-        assert_eq!(list.get_front().unwrap(), ref2);
-        let tmp = list.pop_front().unwrap();
-        // list is empty, tmp: 2
-        assert_eq!(tmp.as_ref(), ref2);
+        assert_eq!(values(list), [1, 2]);
+        let tmp = list.remove_next(list.get_front().unwrap()).unwrap();
+        assert_eq!(tmp.data, 2);
+        list.push_front(tmp);
+        // list: 2, 1
+        assert_eq!(list.pop_front().unwrap().data, 2);
+        assert_eq!(list.pop_front().unwrap().data, 1);
         assert!(list.get_front().is_none());
         assert!(list.pop_front().is_none());
     }
 
     fn test_pop_back<Ref>(
-        list: &mut List<Data, Ref>,
+        list: &mut List<Data, Ref, DataNode>,
         val1: Ref, val2: Ref, val3: Ref, val4: Ref)
     where
-        Ref: Refer<Ent<Data>> + AsRef<Ent<Data>>,
+        Ref: Refer<Data>,
     {
-        let ref1 = unsafe { &*(val1.as_ref() as *const Ent<Data>) };
-        let ref2 = unsafe { &*(val2.as_ref() as *const Ent<Data>) };
-        let ref3 = unsafe { &*(val3.as_ref() as *const Ent<Data>) };
-        let ref4 = unsafe { &*(val4.as_ref() as *const Ent<Data>) };
-
-        assert!(list.get_front().is_none());
-        assert!(list.get_back().is_none());
         assert!(list.pop_back().is_none());
         list.push_front(val1);
-        // list: 1
-        assert_eq!(list.get_front().unwrap(), ref1);
-        assert_eq!(list.get_back().unwrap(), ref1);
         list.push_front(val2);
-        // list: 2, 1
-        assert_eq!(list.get_front().unwrap(), ref2);
-        assert_eq!(list.get_back().unwrap(), ref1);
         list.push_back(val3);
-        // list: 2, 1, 3
-        assert_eq!(list.get_front().unwrap(), ref2);
-        assert_eq!(list.get_back().unwrap(), ref3);
         list.push_back(val4);
         // list: 2, 1, 3, 4
-        for (x, y) in [2, 1, 3, 4, -1].iter().zip(list.iter()) {
-            assert_eq!(*x, y.data);
+        let mut iter = list.iter().rev();
+        for x in [4, 3, 1, 2].iter() {
+            assert_eq!(iter.next().unwrap().data, *x);
         }
-        assert_eq!(list.get_front().unwrap(), ref2);
-        assert_eq!(list.get_back().unwrap(), ref4);
+        assert!(iter.next().is_none());
 
         let tmp = list.pop_back().unwrap();
         // list: 2, 1, 3, tmp: 4
-        assert_eq!(tmp.as_ref(), ref4);
-        assert_eq!(list.get_front().unwrap(), ref2);
-        assert_eq!(list.get_back().unwrap(), ref3);
-        list.insert_after(list.get_front().unwrap(), tmp);
-        // list: 2, 4, 1, 3
-        assert_eq!(list.get_front().unwrap(), ref2);
-        assert_eq!(list.get_back().unwrap(), ref3);
+        assert_eq!(tmp.data, 4);
+        list.insert_before(list.get_front().unwrap(), tmp);
+        // list: 4, 2, 1, 3
+        assert_eq!(values(list), [4, 2, 1, 3]);
         let tmp = list.pop_back().unwrap();
-        // list: 2, 4, 1, tmp: 3
-        assert_eq!(tmp.as_ref(), ref3);
-        assert_eq!(list.get_front().unwrap(), ref2);
-        assert_eq!(list.get_back().unwrap(), ref1);
+        // list: 4, 2, 1, tmp: 3
         list.insert_before(list.get_back().unwrap(), tmp);
-        // list: 2, 4, 3, 1
-        assert_eq!(list.get_front().unwrap(), ref2);
-        assert_eq!(list.get_back().unwrap(), ref1);
-        let tmp = list.pop_back().unwrap();
-        // list: 2, 4, 3, tmp: 1
-        assert_eq!(tmp.as_ref(), ref1);
-        assert_eq!(list.get_front().unwrap(), ref2);
-        assert_eq!(list.get_back().unwrap(), ref3);
-        let tmp = list.pop_back().unwrap();
-        // list: 2, 4, tmp: 3
-        assert_eq!(tmp.as_ref(), ref3);
-        assert_eq!(list.get_front().unwrap(), ref2);
-        assert_eq!(list.get_back().unwrap(), ref4);
-        let tmp = list.pop_back().unwrap();
-        // list: 2, tmp: 4
-        assert_eq!(tmp.as_ref(), ref4);
-        assert_eq!(list.get_front().unwrap(), ref2);
-        assert_eq!(list.get_back().unwrap(), ref2);
-        let tmp = list.pop_back().unwrap();
-        // list is empty, tmp: 2
-        assert_eq!(tmp.as_ref(), ref2);
+        // list: 4, 2, 3, 1
+        assert_eq!(values(list), [4, 2, 3, 1]);
+        let back = list.get_back().unwrap();
+        assert_eq!(List::<Data, Ref, DataNode>::get_prev(back).unwrap().data, 3);
+
+        let tmp = list.remove(list.get_next(list.get_front().unwrap()).unwrap());
+        // list: 4, 3, 1, tmp: 2
+        assert_eq!(tmp.data, 2);
+        let tmp = list.remove(list.get_front().unwrap());
+        // list: 3, 1, tmp: 4
+        assert_eq!(tmp.data, 4);
+        assert!(List::<Data, Ref, DataNode>::get_prev(
+            list.get_front().unwrap()).is_none());
+        let tmp = list.remove(list.get_back().unwrap());
+        // list: 3, tmp: 1
+        assert_eq!(tmp.data, 1);
+        assert_eq!(list.get_back().unwrap().data, 3);
+        assert_eq!(list.pop_back().unwrap().data, 3);
         assert!(list.get_front().is_none());
         assert!(list.get_back().is_none());
         assert!(list.pop_back().is_none());
     }
 
-    fn test_remove<Ref>(
-        list: &mut List<Data, Ref>,
-        val1: Ref, val2: Ref, val3: Ref, val4: Ref)
-    where
-        Ref: Refer<Ent<Data>> + AsRef<Ent<Data>>,
-    {
-        let ref1 = unsafe { &*(val1.as_ref() as *const Ent<Data>) };
-        let ref2 = unsafe { &*(val2.as_ref() as *const Ent<Data>) };
-        let ref3 = unsafe { &*(val3.as_ref() as *const Ent<Data>) };
-        let ref4 = unsafe { &*(val4.as_ref() as *const Ent<Data>) };
-
-        list.push_front(val4);
-        list.push_front(val3);
-        list.push_front(val2);
-        list.push_front(val1);
-        for (x, y) in [1, 2, 3, 4, -1].iter().zip(list.iter()) {
-            assert_eq!(*x, y.data);
-        }
-
-        let tmp = list.remove(ref2);
-        assert_eq!(tmp.as_ref(), ref2);
-        for (x, y) in [1, 3, 4, -1].iter().zip(list.iter()) {
-            assert_eq!(*x, y.data);
-        }
-
-        let tmp = list.remove(ref1);
-        assert_eq!(tmp.as_ref(), ref1);
-        for (x, y) in [3, 4, -1].iter().zip(list.iter()) {
-            assert_eq!(*x, y.data);
-        }
-
-        let tmp = list.remove(ref4);
-        assert_eq!(tmp.as_ref(), ref4);
-        for (x, y) in [3, -1].iter().zip(list.iter()) {
-            assert_eq!(*x, y.data);
-        }
-
-        let tmp = list.remove(ref3);
-        assert_eq!(tmp.as_ref(), ref3);
-        assert_eq!(list.get_front(), None);
-    }
-
     #[test]
     fn test_ref() {
-        let mut list = List::<Data, &mut Ent<Data>>::new();
-
-        let mut val1 = Ent::new(Data { data: 1 });
-        let mut val2 = Ent::new(Data { data: 2 });
-        let mut val3 = Ent::new(Data { data: 3 });
-        let mut val4 = Ent::new(Data { data: 4 });
-        test_pop_front(
-            &mut list, &mut val1, &mut val2, &mut val3, &mut val4);
-
-        let mut val1 = Ent::new(Data { data: 1 });
-        let mut val2 = Ent::new(Data { data: 2 });
-        let mut val3 = Ent::new(Data { data: 3 });
-        let mut val4 = Ent::new(Data { data: 4 });
-        test_pop_back(
-            &mut list, &mut val1, &mut val2, &mut val3, &mut val4);
-
-        let mut val1 = Ent::new(Data { data: 1 });
-        let mut val2 = Ent::new(Data { data: 2 });
-        let mut val3 = Ent::new(Data { data: 3 });
-        let mut val4 = Ent::new(Data { data: 4 });
-        test_remove(
-            &mut list, &mut val1, &mut val2, &mut val3, &mut val4);
-    }
+        let mut val: Vec<Data> = (1..=4).map(Data::new).collect();
+        let p = val.as_mut_ptr();
+        let r = |i: usize| unsafe { &mut *p.add(i) };
 
-    #[test]
-    fn test_box() {
-        let mut list = List::<Data, Box<Ent<Data>>>::new();
-
-        let val1 = Box::new(Ent::new(Data { data: 1 }));
-        let val2 = Box::new(Ent::new(Data { data: 2 }));
-        let val3 = Box::new(Ent::new(Data { data: 3 }));
-        let val4 = Box::new(Ent::new(Data { data: 4 }));
-        test_pop_front(&mut list, val1, val2, val3, val4);
-
-        let val1 = Box::new(Ent::new(Data { data: 1 }));
-        let val2 = Box::new(Ent::new(Data { data: 2 }));
-        let val3 = Box::new(Ent::new(Data { data: 3 }));
-        let val4 = Box::new(Ent::new(Data { data: 4 }));
-        test_pop_back(&mut list, val1, val2, val3, val4);
-
-        let val1 = Box::new(Ent::new(Data { data: 1 }));
-        let val2 = Box::new(Ent::new(Data { data: 2 }));
-        let val3 = Box::new(Ent::new(Data { data: 3 }));
-        let val4 = Box::new(Ent::new(Data { data: 4 }));
-        test_remove(&mut list, val1, val2, val3, val4);
+        let mut list = List::<Data, &mut Data, DataNode>::new();
+        test_pop_front(&mut list, r(0), r(1), r(2), r(3));
+        test_pop_back(&mut list, r(0), r(1), r(2), r(3));
+        let mut list = SingleForwardList::<Data, &mut Data, DataFNode>::new();
+        test_s_pop_front(&mut list, r(0), r(1));
     }
 
+    #[cfg(not(feature = "nobox"))]
     #[test]
-    fn test_raw() {
-        let mut list = List::<Data, RawRefer<Ent<Data>>>::new();
-
-        let mut val = [
-            Ent::new(Data { data: 1 }),
-            Ent::new(Data { data: 2 }),
-            Ent::new(Data { data: 3 }),
-            Ent::new(Data { data: 4 }),
-        ];
-
-        test_pop_front(
-            &mut list,
-            (&mut val[0]).into(),
-            (&mut val[1]).into(),
-            (&mut val[2]).into(),
-            (&mut val[3]).into());
-
-        test_pop_back(
-            &mut list,
-            (&mut val[0]).into(),
-            (&mut val[1]).into(),
-            (&mut val[2]).into(),
-            (&mut val[3]).into());
-
-        test_remove(
-            &mut list,
-            (&mut val[0]).into(),
-            (&mut val[1]).into(),
-            (&mut val[2]).into(),
-            (&mut val[3]).into());
-    }
-
-    #[test]
-    fn test_s_ref() {
-        let mut list = SingleList::<Data, &mut Ent<Data>>::new();
-        let mut val1 = Ent::new(Data { data: 1 });
-        let mut val2 = Ent::new(Data { data: 2 });
-
-        test_s_pop_front(&mut list, &mut val1, &mut val2);
-    }
-
-    #[test]
-    fn test_s_box() {
-        let mut list = SingleList::<Data, Box<Ent<Data>>>::new();
-        let val1 = Box::new(Ent::new(Data { data: 1 }));
-        let val2 = Box::new(Ent::new(Data { data: 2 }));
-
-        test_s_pop_front(&mut list, val1, val2);
-    }
-
-    #[test]
-    fn test_s_raw() {
-        let mut list = SingleList::<Data, RawRefer<Ent<Data>>>::new();
-        let mut val = [
-            Ent::new(Data { data: 1 }),
-            Ent::new(Data { data: 2})
-        ];
-
-        test_s_pop_front(&mut list, (&mut val[0]).into(), (&mut val[1]).into());
-    }
-
-    #[test]
-    fn test_f_ref() {
-        let mut list = ForwardList::<Data, &mut ForwardEnt<Data>>::new();
-        let mut val1 = ForwardEnt::new(Data { data: 1 });
-        let mut val2 = ForwardEnt::new(Data { data: 2 });
-        let mut val3 = ForwardEnt::new(Data { data: 3 });
-        let mut val4 = ForwardEnt::new(Data { data: 4 });
-
-        test_f_pop_front(&mut list, &mut val1, &mut val2, &mut val3, &mut val4);
-    }
-
-    #[test]
-    fn test_f_box() {
-        let mut list = ForwardList::<Data, Box<ForwardEnt<Data>>>::new();
-        let val1 = Box::new(ForwardEnt::new(Data { data: 1 }));
-        let val2 = Box::new(ForwardEnt::new(Data { data: 2 }));
-        let val3 = Box::new(ForwardEnt::new(Data { data: 3 }));
-        let val4 = Box::new(ForwardEnt::new(Data { data: 4 }));
-
-        test_f_pop_front(&mut list, val1, val2, val3, val4);
-    }
-
-    #[test]
-    fn test_f_raw() {
-        let mut list = ForwardList::<Data, RawRefer<ForwardEnt<Data>>>::new();
-        let mut val = [
-            ForwardEnt::new(Data { data: 1 }),
-            ForwardEnt::new(Data { data: 2 }),
-            ForwardEnt::new(Data { data: 3 }),
-            ForwardEnt::new(Data { data: 4 })
-        ];
-
-        test_f_pop_front(
-            &mut list,
-            (&mut val[0]).into(),
-            (&mut val[1]).into(),
-            (&mut val[2]).into(),
-            (&mut val[3]).into());
-    }
-
-    #[test]
-    fn test_sf_ref() {
-        let mut list = SingleForwardList::<Data, &mut ForwardEnt<Data>>::new();
-        let mut val1 = ForwardEnt::new(Data { data: 1 });
-        let mut val2 = ForwardEnt::new(Data { data: 2 });
+    fn test_box() {
+        let mut list = List::<Data, Box<Data>, DataNode>::new();
+        let new = |data| Box::new(Data::new(data));
+        test_pop_front(&mut list, new(1), new(2), new(3), new(4));
+        test_pop_back(&mut list, new(1), new(2), new(3), new(4));
 
-        test_sf_pop_front(&mut list, &mut val1, &mut val2);
+        let mut list = SingleList::<Data, Box<Data>, DataNode>::new();
+        test_s_pop_front(&mut list, new(1), new(2));
+        let mut list = ForwardList::<Data, Box<Data>, DataFNode>::new();
+        test_pop_front(&mut list, new(1), new(2), new(3), new(4));
     }
 
     #[test]
-    fn test_sf_box() {
-        let mut list = SingleForwardList::<Data, Box<ForwardEnt<Data>>>::new();
-        let val1 = Box::new(ForwardEnt::new(Data { data: 1 }));
-        let val2 = Box::new(ForwardEnt::new(Data { data: 2 }));
+    fn test_raw() {
+        let mut val: Vec<Data> = (1..=4).map(Data::new).collect();
+        let p = val.as_mut_ptr();
+        let raw = |i: usize| RawRefer::new(unsafe { &mut *p.add(i) });
 
-        test_sf_pop_front(&mut list, val1, val2);
+        let mut list = List::<Data, RawRefer<Data>, DataNode>::new();
+        test_pop_front(&mut list, raw(0), raw(1), raw(2), raw(3));
+        test_pop_back(&mut list, raw(0), raw(1), raw(2), raw(3));
+        let mut list =
+            SingleForwardList::<Data, RawRefer<Data>, DataFNode>::new();
+        test_s_pop_front(&mut list, raw(0), raw(1));
     }
 
+    /// The same elements are linked to two lists at once.
     #[test]
-    fn test_sf_raw() {
-        let mut list =
-            SingleForwardList::<Data, RawRefer<ForwardEnt<Data>>>::new();
-        let mut val = [
-            ForwardEnt::new(Data { data: 1 }),
-            ForwardEnt::new(Data { data: 2 })
-        ];
-
-        test_sf_pop_front(
-            &mut list, (&mut val[0]).into(), (&mut val[1]).into());
+    fn test_multi() {
+        let mut val: Vec<Data> = (1..=4).map(Data::new).collect();
+        let p = val.as_mut_ptr();
+        let raw = |i: usize| RawRefer::new(unsafe { &mut *p.add(i) });
+
+        let mut run = List::<Data, RawRefer<Data>, DataNode>::new();
+        let mut wait = ForwardList::<Data, RawRefer<Data>, DataFNode>::new();
+        for i in 0..4 {
+            run.push_back(raw(i));
+        }
+        wait.push_back(raw(3));
+        wait.push_back(raw(1));
+        assert_eq!(values(&mut run), [1, 2, 3, 4]);
+        assert_eq!(values(&mut wait), [4, 2]);
+
+        // removing from one list keeps the other.
+        run.remove(unsafe { &*p.add(1) });
+        assert_eq!(values(&mut run), [1, 3, 4]);
+        assert_eq!(values(&mut wait), [4, 2]);
+
+        let mut x = wait.pop_front().unwrap();
+        x.as_mut().data = 40;
+        assert_eq!(values(&mut run), [1, 3, 40]);
+        assert_eq!(values(&mut wait), [2]);
+        wait.push_front(run.pop_front().unwrap());
+        assert_eq!(values(&mut run), [3, 40]);
+        assert_eq!(values(&mut wait), [1, 2]);
+        run.push_front(wait.pop_front().unwrap());
+        assert_eq!(values(&mut run), [1, 3, 40]);
+        assert_eq!(values(&mut wait), [2]);
     }
-*/
 }
@@ -0,0 +1,185 @@
+// Uniqos  --  Unique Operating System
+// (c) 2019 KATO Takeshi
+// Released under the MIT license
+
+//! One-time initialization for statics.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::hint;
+use core::mem::MaybeUninit;
+use core::ops::Deref;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+
+const INCOMPLETE: u8 = 0;
+const RUNNING: u8 = 1;
+const COMPLETE: u8 = 2;
+
+/// The value is made by the first `call_once()`.  Other callers spin
+/// until it is made.
+pub struct Once<T> {
+    state: AtomicU8,
+    data: UnsafeCell<MaybeUninit<T>>,
+}
+
+unsafe impl<T: Send> Send for Once<T> {}
+unsafe impl<T: Send + Sync> Sync for Once<T> {}
+
+impl<T> Default for Once<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Once<T> {
+    pub const fn new() -> Self {
+        Once {
+            state: AtomicU8::new(INCOMPLETE),
+            data: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// If `f` panics, later calls spin forever.
+    pub fn call_once<F: FnOnce() -> T>(&self, f: F) -> &T {
+        match self.state.compare_exchange(
+            INCOMPLETE, RUNNING, Ordering::Acquire, Ordering::Acquire)
+        {
+            Ok(_) => {
+                unsafe { (*self.data.get()).as_mut_ptr().write(f()) };
+                self.state.store(COMPLETE, Ordering::Release);
+            },
+            Err(_) => {
+                while self.state.load(Ordering::Acquire) != COMPLETE {
+                    hint::spin_loop();
+                }
+            },
+        }
+        unsafe { self._get() }
+    }
+
+    /// None until the value is made.
+    pub fn get(&self) -> Option<&T> {
+        if self.is_completed() {
+            Some(unsafe { self._get() })
+        } else {
+            None
+        }
+    }
+
+    pub fn is_completed(&self) -> bool {
+        self.state.load(Ordering::Acquire) == COMPLETE
+    }
+
+    unsafe fn _get(&self) -> &T {
+        &*(*self.data.get()).as_ptr()
+    }
+}
+
+impl<T> Drop for Once<T> {
+    fn drop(&mut self) {
+        if *self.state.get_mut() == COMPLETE {
+            unsafe { (*self.data.get()).as_mut_ptr().drop_in_place() };
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Once<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.get() {
+            Some(v) => write!(f, "Once({:?})", v),
+            None => write!(f, "Once(<uninit>)"),
+        }
+    }
+}
+
+/// The value is made by `init` at the first access.
+///
+/// static TABLE: Lazy<[u8; 256]> = Lazy::new(make_table);
+pub struct Lazy<T, F = fn() -> T> {
+    once: Once<T>,
+    init: UnsafeCell<Option<F>>,
+}
+
+// `init` is taken only by the first caller of Once::call_once().
+unsafe impl<T: Send + Sync, F: Send> Sync for Lazy<T, F> {}
+
+impl<T, F: FnOnce() -> T> Lazy<T, F> {
+    pub const fn new(init: F) -> Self {
+        Lazy {
+            once: Once::new(),
+            init: UnsafeCell::new(Some(init)),
+        }
+    }
+
+    pub fn force(this: &Self) -> &T {
+        this.once.call_once(|| {
+            let init = unsafe { (*this.init.get()).take() };
+            init.unwrap()()
+        })
+    }
+}
+
+impl<T, F: FnOnce() -> T> Deref for Lazy<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        Lazy::force(self)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+    use core::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+    use std::thread;
+    use std::vec::Vec;
+
+    use super::*;
+
+    #[test]
+    fn test_once() {
+        let once = Once::new();
+        assert_eq!(once.get(), None);
+        assert_eq!(*once.call_once(|| 1), 1);
+        assert_eq!(*once.call_once(|| 2), 1);
+        assert_eq!(once.get(), Some(&1));
+
+        let once = Arc::new(Once::new());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let threads: Vec<_> = (0..4).map(|i| {
+            let once = once.clone();
+            let calls = calls.clone();
+            thread::spawn(move || {
+                *once.call_once(|| {
+                    calls.fetch_add(1, Ordering::Relaxed);
+                    i
+                })
+            })
+        }).collect();
+        for t in threads {
+            // every thread sees the value of the first caller.
+            assert_eq!(t.join().unwrap(), *once.get().unwrap());
+        }
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+    }
+
+    static LAZY_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    fn make_table() -> [u8; 4] {
+        LAZY_CALLS.fetch_add(1, Ordering::Relaxed);
+        [1, 2, 3, 4]
+    }
+
+    static TABLE: Lazy<[u8; 4]> = Lazy::new(make_table);
+
+    #[test]
+    fn test_lazy() {
+        assert_eq!(LAZY_CALLS.load(Ordering::Relaxed), 0);
+        assert_eq!(TABLE[2], 3);
+        assert_eq!(*TABLE, [1, 2, 3, 4]);
+        assert_eq!(LAZY_CALLS.load(Ordering::Relaxed), 1);
+    }
+}
@@ -4,12 +4,281 @@
 
 /// Misc operations.
 
+use core::fmt;
+
+
 /// `align` must be a power of two.
 pub fn down_align(adr: usize, align: usize) -> usize {
+    debug_assert!(align.is_power_of_two());
     adr & !(align - 1)
 }
-pub fn up_align(adr: usize, align: usize) -> usize {
-    (adr + align - 1) & !(align - 1)
+/// None if the result overflows.
+pub fn up_align(adr: usize, align: usize) -> Option<usize> {
+    debug_assert!(align.is_power_of_two());
+    Some(adr.checked_add(align - 1)? & !(align - 1))
+}
+pub fn is_aligned(adr: usize, align: usize) -> bool {
+    debug_assert!(align.is_power_of_two());
+    adr & (align - 1) == 0
+}
+
+pub const PAGE_SHIFT: u32 = 12;
+pub const PAGE_SIZE: usize = 1 << PAGE_SHIFT;
+
+// Address types.
+// PhysAddr and VirtAddr are not converted to each other implicitly; only
+// the code which knows the mapping converts them.
+
+macro_rules! addr_type {
+    ($(#[$attr:meta])* $name:ident, $int:ty, $range:ident) => {
+        $(#[$attr])*
+        #[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        #[repr(transparent)]
+        pub struct $name($int);
+
+        impl $name {
+            pub const fn new(adr: $int) -> Self {
+                $name(adr)
+            }
+            pub const fn get(self) -> $int {
+                self.0
+            }
+            /// `align` must be a power of two.
+            pub fn is_aligned(self, align: $int) -> bool {
+                debug_assert!(align.is_power_of_two());
+                self.0 & (align - 1) == 0
+            }
+            pub fn down_align(self, align: $int) -> Self {
+                debug_assert!(align.is_power_of_two());
+                $name(self.0 & !(align - 1))
+            }
+            /// None if the result overflows.
+            pub fn up_align(self, align: $int) -> Option<Self> {
+                debug_assert!(align.is_power_of_two());
+                Some($name(self.0.checked_add(align - 1)? & !(align - 1)))
+            }
+            pub fn checked_add(self, bytes: $int) -> Option<Self> {
+                Some($name(self.0.checked_add(bytes)?))
+            }
+            pub fn checked_sub(self, bytes: $int) -> Option<Self> {
+                Some($name(self.0.checked_sub(bytes)?))
+            }
+            /// Bytes from `base` to self.  None if self is below `base`.
+            pub fn offset_from(self, base: Self) -> Option<$int> {
+                self.0.checked_sub(base.0)
+            }
+        }
+
+        impl From<$int> for $name {
+            fn from(adr: $int) -> Self {
+                $name(adr)
+            }
+        }
+
+        impl From<$name> for $int {
+            fn from(adr: $name) -> Self {
+                adr.0
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, concat!(stringify!($name), "({:#x})"), self.0)
+            }
+        }
+
+        impl fmt::LowerHex for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::LowerHex::fmt(&self.0, f)
+            }
+        }
+
+        /// Address range.  The end is inclusive, so that the range can
+        /// reach the top of the address space.
+        #[derive(Clone, Copy, PartialEq, Eq)]
+        pub struct $range {
+            start: $name,
+            end: $name,
+        }
+
+        impl $range {
+            /// None if `end` is below `start`.
+            pub fn new(start: $name, end: $name) -> Option<Self> {
+                if start <= end {
+                    Some($range { start, end })
+                } else {
+                    None
+                }
+            }
+            /// None if `bytes` is 0 or the range overflows.
+            pub fn from_bytes(start: $name, bytes: $int) -> Option<Self> {
+                let end = start.checked_add(bytes.checked_sub(1)?)?;
+                Some($range { start, end })
+            }
+            pub fn start(&self) -> $name {
+                self.start
+            }
+            /// Inclusive.
+            pub fn end(&self) -> $name {
+                self.end
+            }
+            /// None if the range covers the whole address space.
+            pub fn bytes(&self) -> Option<$int> {
+                (self.end.0 - self.start.0).checked_add(1)
+            }
+            pub fn contains(&self, adr: $name) -> bool {
+                self.start <= adr && adr <= self.end
+            }
+            pub fn intersect(&self, other: &Self) -> Option<Self> {
+                Self::new(
+                    core::cmp::max(self.start, other.start),
+                    core::cmp::min(self.end, other.end))
+            }
+        }
+
+        impl fmt::Debug for $range {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, concat!(stringify!($range), "({:#x}-{:#x})"),
+                       self.start.0, self.end.0)
+            }
+        }
+    };
 }
 
+macro_rules! page_type {
+    ($(#[$attr:meta])* $name:ident, $addr:ident, $int:ty) => {
+        $(#[$attr])*
+        #[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        #[repr(transparent)]
+        pub struct $name($int);
 
+        impl $name {
+            pub const fn new(num: $int) -> Self {
+                $name(num)
+            }
+            pub const fn number(self) -> $int {
+                self.0
+            }
+            /// The page which contains `adr`.
+            pub const fn containing(adr: $addr) -> Self {
+                $name(adr.0 >> PAGE_SHIFT)
+            }
+            /// None if `adr` is not aligned to the page.
+            pub fn from_start(adr: $addr) -> Option<Self> {
+                if adr.is_aligned(PAGE_SIZE as $int) {
+                    Some(Self::containing(adr))
+                } else {
+                    None
+                }
+            }
+            /// None if the address overflows.
+            pub fn start(self) -> Option<$addr> {
+                if self.0 > (<$int>::MAX >> PAGE_SHIFT) {
+                    None
+                } else {
+                    Some($addr(self.0 << PAGE_SHIFT))
+                }
+            }
+            pub fn checked_add(self, n: $int) -> Option<Self> {
+                Some($name(self.0.checked_add(n)?))
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, concat!(stringify!($name), "({:#x})"), self.0)
+            }
+        }
+    };
+}
+
+addr_type!(
+    /// Physical address.  It may not be accessible without a mapping.
+    PhysAddr, u64, PhysRange);
+addr_type!(
+    /// Virtual address.
+    VirtAddr, usize, VirtRange);
+page_type!(
+    /// Physical page frame number.
+    Frame, PhysAddr, u64);
+page_type!(
+    /// Virtual page number.
+    Page, VirtAddr, usize);
+
+impl VirtAddr {
+    pub fn from_ptr<T: ?Sized>(ptr: *const T) -> Self {
+        VirtAddr(ptr as *const u8 as usize)
+    }
+    pub const fn as_ptr<T>(self) -> *const T {
+        self.0 as *const T
+    }
+    pub const fn as_mut_ptr<T>(self) -> *mut T {
+        self.0 as *mut T
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_align() {
+        assert_eq!(down_align(0x1234, 0x100), 0x1200);
+        assert_eq!(up_align(0x1234, 0x100), Some(0x1300));
+        assert_eq!(up_align(0x1200, 0x100), Some(0x1200));
+        assert_eq!(up_align(usize::MAX - 2, 4), None);
+        assert!(is_aligned(0x1000, 0x1000));
+
+        let a = PhysAddr::new(0x1234);
+        assert_eq!(a.down_align(0x1000), PhysAddr::new(0x1000));
+        assert_eq!(a.up_align(0x1000), Some(PhysAddr::new(0x2000)));
+        assert_eq!(PhysAddr::new(u64::MAX).up_align(2), None);
+        assert!(!a.is_aligned(8));
+        assert_eq!(a.checked_add(0x10), Some(PhysAddr::new(0x1244)));
+        assert_eq!(a.checked_sub(0x1235), None);
+        assert_eq!(a.offset_from(PhysAddr::new(0x1000)), Some(0x234));
+        assert_eq!(u64::from(a), 0x1234);
+    }
+
+    #[test]
+    fn test_page() {
+        let f = Frame::containing(PhysAddr::new(0x12345));
+        assert_eq!(f.number(), 0x12);
+        assert_eq!(f.start(), Some(PhysAddr::new(0x12000)));
+        assert_eq!(Frame::from_start(PhysAddr::new(0x12345)), None);
+        assert_eq!(Frame::from_start(PhysAddr::new(0x12000)), Some(f));
+        assert_eq!(Frame::new(u64::MAX).start(), None);
+        assert_eq!(Page::containing(VirtAddr::new(0x2fff)), Page::new(2));
+
+        let x = 1u32;
+        let v = VirtAddr::from_ptr(&x);
+        assert_eq!(v.as_ptr::<u32>(), &x as *const u32);
+    }
+
+    #[test]
+    fn test_range() {
+        let r = PhysRange::from_bytes(PhysAddr::new(0x1000), 0x1000).unwrap();
+        assert_eq!(r.end(), PhysAddr::new(0x1fff));
+        assert_eq!(r.bytes(), Some(0x1000));
+        assert!(r.contains(PhysAddr::new(0x1fff)));
+        assert!(!r.contains(PhysAddr::new(0x2000)));
+        assert!(PhysRange::from_bytes(PhysAddr::new(1), 0).is_none());
+        assert!(PhysRange::from_bytes(PhysAddr::new(u64::MAX), 2).is_none());
+        assert!(PhysRange::new(PhysAddr::new(2), PhysAddr::new(1)).is_none());
+
+        let all = VirtRange::new(VirtAddr::new(0), VirtAddr::new(usize::MAX))
+            .unwrap();
+        assert_eq!(all.bytes(), None);
+        let r = VirtRange::new(VirtAddr::new(0x100), VirtAddr::new(0x1ff))
+            .unwrap();
+        assert_eq!(all.intersect(&r), Some(r));
+        let r2 = VirtRange::new(VirtAddr::new(0x180), VirtAddr::new(0x2ff))
+            .unwrap();
+        assert_eq!(r.intersect(&r2),
+                   VirtRange::new(VirtAddr::new(0x180), VirtAddr::new(0x1ff)));
+        let r3 = VirtRange::new(VirtAddr::new(0x200), VirtAddr::new(0x2ff))
+            .unwrap();
+        assert_eq!(r.intersect(&r3), None);
+    }
+}
@@ -0,0 +1,479 @@
+// Uniqos  --  Unique Operating System
+// (c) 2019 KATO Takeshi
+// Released under the MIT license
+
+//! Spin locks.
+//!
+//! SpinLock is a ticket lock, so waiters get the lock in arrival order.
+//! IrqSpinLock also disables interrupts while it is held, so it can be
+//! taken in interrupt handlers.  The interrupt control is given by the
+//! arch code through `set_irq_ops()`.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::hint;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+
+/// Interrupt control of the CPU.
+pub struct IrqOps {
+    /// Disable interrupts and return the previous state.
+    pub save: fn() -> usize,
+    /// Restore the state returned by `save`.
+    pub restore: fn(usize),
+}
+
+static IRQ_OPS: AtomicPtr<IrqOps> = AtomicPtr::new(ptr::null_mut());
+
+/// Set the interrupt control used by IrqSpinLock.
+/// Until this is called, IrqSpinLock does not touch interrupts.
+pub fn set_irq_ops(ops: &'static IrqOps) {
+    IRQ_OPS.store(ops as *const IrqOps as *mut IrqOps, Ordering::Release);
+}
+
+fn irq_save() -> usize {
+    let ops = IRQ_OPS.load(Ordering::Acquire);
+    match unsafe { ops.as_ref() } {
+        Some(ops) => (ops.save)(),
+        None => 0,
+    }
+}
+
+fn irq_restore(flags: usize) {
+    let ops = IRQ_OPS.load(Ordering::Acquire);
+    if let Some(ops) = unsafe { ops.as_ref() } {
+        (ops.restore)(flags);
+    }
+}
+
+// SpinLock
+
+pub struct SpinLock<T: ?Sized> {
+    next: AtomicUsize,
+    serving: AtomicUsize,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for SpinLock<T> {}
+unsafe impl<T: ?Sized + Send> Sync for SpinLock<T> {}
+
+impl<T> SpinLock<T> {
+    pub const fn new(data: T) -> Self {
+        SpinLock {
+            next: AtomicUsize::new(0),
+            serving: AtomicUsize::new(0),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> SpinLock<T> {
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        self._lock();
+        SpinLockGuard { lock: self }
+    }
+
+    /// None if the lock is held.
+    pub fn try_lock(&self) -> Option<SpinLockGuard<'_, T>> {
+        if self._try_lock() {
+            Some(SpinLockGuard { lock: self })
+        } else {
+            None
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.next.load(Ordering::Relaxed) !=
+            self.serving.load(Ordering::Relaxed)
+    }
+
+    /// No locking is needed because `self` is borrowed mutably.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    /// Pointer to the data.  Accesses through it are not protected.
+    pub fn as_mut_ptr(&self) -> *mut T {
+        self.data.get()
+    }
+
+    fn _lock(&self) {
+        let ticket = self.next.fetch_add(1, Ordering::Relaxed);
+        while self.serving.load(Ordering::Acquire) != ticket {
+            hint::spin_loop();
+        }
+    }
+
+    fn _try_lock(&self) -> bool {
+        let ticket = self.serving.load(Ordering::Relaxed);
+        self.next.compare_exchange(
+            ticket, ticket.wrapping_add(1),
+            Ordering::Acquire, Ordering::Relaxed).is_ok()
+    }
+
+    fn _unlock(&self) {
+        self.serving.fetch_add(1, Ordering::Release);
+    }
+}
+
+impl<T: Default> Default for SpinLock<T> {
+    fn default() -> Self {
+        SpinLock::new(T::default())
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for SpinLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => write!(f, "SpinLock({:?})", &*guard),
+            None => write!(f, "SpinLock(<locked>)"),
+        }
+    }
+}
+
+pub struct SpinLockGuard<'a, T: ?Sized> {
+    lock: &'a SpinLock<T>,
+}
+
+impl<T: ?Sized> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for SpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock._unlock();
+    }
+}
+
+// IrqSpinLock
+
+pub struct IrqSpinLock<T: ?Sized> {
+    lock: SpinLock<T>,
+}
+
+impl<T> IrqSpinLock<T> {
+    pub const fn new(data: T) -> Self {
+        IrqSpinLock {
+            lock: SpinLock::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.lock.into_inner()
+    }
+}
+
+impl<T: ?Sized> IrqSpinLock<T> {
+    /// Interrupts are disabled until the guard is dropped.
+    pub fn lock(&self) -> IrqSpinLockGuard<'_, T> {
+        let flags = irq_save();
+        self.lock._lock();
+        IrqSpinLockGuard { lock: self, flags }
+    }
+
+    /// None if the lock is held.  Interrupts are not changed then.
+    pub fn try_lock(&self) -> Option<IrqSpinLockGuard<'_, T>> {
+        let flags = irq_save();
+        if self.lock._try_lock() {
+            Some(IrqSpinLockGuard { lock: self, flags })
+        } else {
+            irq_restore(flags);
+            None
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.lock.is_locked()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.lock.get_mut()
+    }
+
+    pub fn as_mut_ptr(&self) -> *mut T {
+        self.lock.as_mut_ptr()
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for IrqSpinLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => write!(f, "IrqSpinLock({:?})", &*guard),
+            None => write!(f, "IrqSpinLock(<locked>)"),
+        }
+    }
+}
+
+pub struct IrqSpinLockGuard<'a, T: ?Sized> {
+    lock: &'a IrqSpinLock<T>,
+    flags: usize,
+}
+
+impl<T: ?Sized> Deref for IrqSpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for IrqSpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for IrqSpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.lock._unlock();
+        irq_restore(self.flags);
+    }
+}
+
+// RwSpinLock
+
+const WRITER: usize = 1;
+const READER: usize = 2;
+
+/// Readers share the lock.  A writer waits until all readers leave, so
+/// continuous readers may starve writers.
+pub struct RwSpinLock<T: ?Sized> {
+    state: AtomicUsize,  // READER * readers | WRITER
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for RwSpinLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwSpinLock<T> {}
+
+impl<T> RwSpinLock<T> {
+    pub const fn new(data: T) -> Self {
+        RwSpinLock {
+            state: AtomicUsize::new(0),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwSpinLock<T> {
+    pub fn read(&self) -> RwSpinLockReadGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_read() {
+                return guard;
+            }
+            while self.state.load(Ordering::Relaxed) & WRITER != 0 {
+                hint::spin_loop();
+            }
+        }
+    }
+
+    /// None if a writer holds the lock.
+    pub fn try_read(&self) -> Option<RwSpinLockReadGuard<'_, T>> {
+        let mut state = self.state.load(Ordering::Relaxed);
+        loop {
+            if state & WRITER != 0 {
+                return None;
+            }
+            match self.state.compare_exchange_weak(
+                state, state + READER, Ordering::Acquire, Ordering::Relaxed)
+            {
+                Ok(_) => return Some(RwSpinLockReadGuard { lock: self }),
+                Err(s) => state = s,
+            }
+        }
+    }
+
+    pub fn write(&self) -> RwSpinLockWriteGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_write() {
+                return guard;
+            }
+            while self.state.load(Ordering::Relaxed) != 0 {
+                hint::spin_loop();
+            }
+        }
+    }
+
+    /// None if a reader or a writer holds the lock.
+    pub fn try_write(&self) -> Option<RwSpinLockWriteGuard<'_, T>> {
+        match self.state.compare_exchange(
+            0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+        {
+            Ok(_) => Some(RwSpinLockWriteGuard { lock: self }),
+            Err(_) => None,
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwSpinLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.try_read() {
+            Some(guard) => write!(f, "RwSpinLock({:?})", &*guard),
+            None => write!(f, "RwSpinLock(<locked>)"),
+        }
+    }
+}
+
+pub struct RwSpinLockReadGuard<'a, T: ?Sized> {
+    lock: &'a RwSpinLock<T>,
+}
+
+impl<T: ?Sized> Deref for RwSpinLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwSpinLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.state.fetch_sub(READER, Ordering::Release);
+    }
+}
+
+pub struct RwSpinLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a RwSpinLock<T>,
+}
+
+impl<T: ?Sized> Deref for RwSpinLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwSpinLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwSpinLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.state.fetch_and(!WRITER, Ordering::Release);
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+    use std::format;
+    use std::sync::Arc;
+    use std::thread;
+    use std::vec::Vec;
+
+    use super::*;
+
+    #[test]
+    fn test_spinlock() {
+        let lock = SpinLock::new(1);
+        {
+            let mut g = lock.lock();
+            *g += 1;
+            assert!(lock.is_locked());
+            assert!(lock.try_lock().is_none());
+        }
+        assert!(!lock.is_locked());
+        assert_eq!(*lock.try_lock().unwrap(), 2);
+
+        let lock = Arc::new(SpinLock::new(0usize));
+        let threads: Vec<_> = (0..4).map(|_| {
+            let lock = lock.clone();
+            thread::spawn(move || {
+                for _ in 0..1000 {
+                    *lock.lock() += 1;
+                }
+            })
+        }).collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(*lock.lock(), 4000);
+    }
+
+    static IRQ_STATE: AtomicUsize = AtomicUsize::new(1);
+
+    fn test_save() -> usize {
+        IRQ_STATE.swap(0, Ordering::Relaxed)
+    }
+
+    fn test_restore(flags: usize) {
+        IRQ_STATE.store(flags, Ordering::Relaxed);
+    }
+
+    static TEST_IRQ_OPS: IrqOps = IrqOps {
+        save: test_save,
+        restore: test_restore,
+    };
+
+    /// Put back the previous IrqOps even if the test fails.
+    struct RestoreIrqOps(*mut IrqOps);
+
+    impl Drop for RestoreIrqOps {
+        fn drop(&mut self) {
+            IRQ_OPS.store(self.0, Ordering::Release);
+        }
+    }
+
+    #[test]
+    fn test_irq() {
+        let _restore = RestoreIrqOps(IRQ_OPS.load(Ordering::Acquire));
+        set_irq_ops(&TEST_IRQ_OPS);
+        let lock = IrqSpinLock::new([0u8; 4]);
+        {
+            let mut g = lock.lock();
+            g[0] = 1;
+            assert_eq!(IRQ_STATE.load(Ordering::Relaxed), 0);
+            assert!(lock.try_lock().is_none());
+            // try_lock does not enable interrupts on failure.
+            assert_eq!(IRQ_STATE.load(Ordering::Relaxed), 0);
+        }
+        assert_eq!(IRQ_STATE.load(Ordering::Relaxed), 1);
+        assert_eq!(lock.into_inner(), [1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_rw() {
+        let lock = RwSpinLock::new(10);
+        {
+            let r1 = lock.read();
+            let r2 = lock.try_read().unwrap();
+            assert_eq!(*r1 + *r2, 20);
+            assert!(lock.try_write().is_none());
+        }
+        {
+            let mut w = lock.write();
+            *w = 11;
+            assert!(lock.try_read().is_none());
+            assert!(lock.try_write().is_none());
+        }
+        assert_eq!(*lock.read(), 11);
+        assert_eq!(format!("{:?}", lock), "RwSpinLock(11)");
+    }
+}
@@ -0,0 +1,76 @@
+// Uniqos  --  Unique Operating System
+// (c) 2019 KATO Takeshi
+// Released under the MIT license
+
+//! Types which can be initialized with zeros.
+//!
+//! Large objects such as CheapAlloc are built by `zeroed()` in const
+//! contexts, so they can be placed in statics without a byte buffer.
+
+use core::cell::{Cell, UnsafeCell};
+use core::marker::PhantomData;
+use core::mem;
+use core::ptr::NonNull;
+use core::sync::atomic::{
+    AtomicBool, AtomicI32, AtomicI64, AtomicU32, AtomicU64, AtomicUsize};
+
+
+/// All-zero bytes are a valid value of the type.
+///
+/// # Safety
+///
+/// The implementer must not have a field which can not be zero, such as
+/// references, NonNull or enums without a zero discriminant.
+pub unsafe trait Zeroable: Sized {}
+
+/// Returns the value of all-zero bytes.
+pub const fn zeroed<T: Zeroable>() -> T {
+    unsafe { mem::zeroed() }
+}
+
+macro_rules! impl_zeroable {
+    ($($t:ty),*) => {
+        $(unsafe impl Zeroable for $t {})*
+    };
+}
+
+impl_zeroable!((), bool, char,
+               u8, u16, u32, u64, u128, usize,
+               i8, i16, i32, i64, i128, isize,
+               AtomicBool, AtomicI32, AtomicI64,
+               AtomicU32, AtomicU64, AtomicUsize);
+
+unsafe impl<T: ?Sized> Zeroable for PhantomData<T> {}
+unsafe impl<T> Zeroable for *const T {}
+unsafe impl<T> Zeroable for *mut T {}
+unsafe impl<T> Zeroable for Option<NonNull<T>> {}
+unsafe impl<T: Zeroable> Zeroable for Cell<T> {}
+unsafe impl<T: Zeroable> Zeroable for UnsafeCell<T> {}
+unsafe impl<T: Zeroable, const N: usize> Zeroable for [T; N] {}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Pair {
+        a: usize,
+        p: *const u8,
+    }
+
+    unsafe impl Zeroable for Pair {}
+
+    const PAIRS: [Pair; 3] = zeroed();
+
+    #[test]
+    fn test_zeroed() {
+        for p in PAIRS.iter() {
+            assert_eq!(*p, Pair { a: 0, p: core::ptr::null() });
+        }
+        let c: Cell<Option<NonNull<u8>>> = zeroed();
+        assert!(c.get().is_none());
+        let x: AtomicUsize = zeroed();
+        assert_eq!(x.into_inner(), 0);
+    }
+}